@@ -0,0 +1,112 @@
+//! Criterion benchmarks for the hot paths, run with `cargo bench`. Criterion
+//! keeps the previous run's results, so each run reports how much a change
+//! sped up or slowed down every case.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use rust_quadtree_art::config::{OutputOptions, QuadConfig};
+use rust_quadtree_art::quad::{subdivide_nodes, subdivide_to_count, Quad};
+use rust_quadtree_art::render::{generate_image, render_to_image};
+
+const SIZES: [u32; 2] = [128, 512];
+/// Canvas and leaf count of the large render case, which times the fill pass
+/// the `parallel` feature splits across threads.
+const LARGE_RENDER_SIZE: (u32, u32) = (1600, 1200);
+const LARGE_RENDER_LEAVES: usize = 100_000;
+
+/// A single flat color, which subdivides into very few leaves.
+fn uniform_image(size: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_pixel(size, size, Rgba([90, 140, 200, 255])))
+}
+
+/// Per-pixel xorshift noise, which subdivides down to the size threshold everywhere.
+fn noisy_image(size: u32) -> DynamicImage {
+    noisy_image_sized(size, size)
+}
+
+fn noisy_image_sized(width: u32, height: u32) -> DynamicImage {
+    let mut state: u32 = 0x9e37_79b9;
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        Rgba([r, g, b, 255])
+    }))
+}
+
+fn stages(c: &mut Criterion) {
+    let output = OutputOptions {
+        output_file: std::env::temp_dir()
+            .join("quadtree_bench.png")
+            .to_string_lossy()
+            .into_owned(),
+        ..OutputOptions::default()
+    };
+    let config = QuadConfig::default();
+
+    for size in SIZES {
+        for (fixture, image) in [
+            ("uniform", uniform_image(size)),
+            ("noisy", noisy_image(size)),
+        ] {
+            let quad = Quad::new(image, 0, 0, size, size, config.clone(), 0);
+            let id = BenchmarkId::new(fixture, format!("{}x{}", size, size));
+
+            c.benchmark_group("calc_avg_color")
+                .bench_with_input(id.clone(), &quad, |b, quad| {
+                    b.iter(|| quad.calc_avg_color())
+                });
+            c.benchmark_group("calc_avg_color_distance")
+                .bench_with_input(id.clone(), &quad, |b, quad| {
+                    b.iter(|| quad.calc_avg_color_distance())
+                });
+            c.benchmark_group("subdivide_nodes")
+                .bench_with_input(id.clone(), &quad, |b, quad| {
+                    b.iter(|| subdivide_nodes(black_box(quad.clone())))
+                });
+            let leaves = subdivide_nodes(quad.clone());
+            c.benchmark_group("generate_image")
+                .bench_with_input(id, &leaves, |b, leaves| {
+                    b.iter(|| {
+                        generate_image(leaves.clone(), size, size, &config, &output)
+                            .expect("Cannot save output image!")
+                    })
+                });
+        }
+    }
+}
+
+fn large_render(c: &mut Criterion) {
+    let (width, height) = LARGE_RENDER_SIZE;
+    let config = QuadConfig {
+        size_threshold: 1,
+        max_depth: 12,
+        outline_color: None,
+        ..QuadConfig::default()
+    };
+    let quad = Quad::new(
+        noisy_image_sized(width, height),
+        0,
+        0,
+        width,
+        height,
+        config.clone(),
+        0,
+    );
+    let leaves = subdivide_to_count(quad, LARGE_RENDER_LEAVES);
+    let id = BenchmarkId::new(
+        format!("{}k leaves", leaves.len() / 1000),
+        format!("{}x{}", width, height),
+    );
+    let mut group = c.benchmark_group("render_to_image");
+    group.sample_size(10);
+    group.bench_with_input(id, &leaves, |b, leaves| {
+        b.iter(|| render_to_image(leaves, width, height, &config))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, stages, large_render);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame};
+use std::fs::File;
+use std::io::BufWriter;
+use std::str::FromStr;
+
+use crate::config::QuadConfig;
+use crate::error::QuadError;
+use crate::quad::{subdivide_to_count_with, Quad};
+use crate::render::render_to_image;
+
+/// Returns the leaf set after each depth level of threshold-based subdivision,
+/// starting with the single root quad.
+pub fn frames_by_depth(initial_quad: Quad) -> Vec<Vec<Quad>> {
+    let mut frames: Vec<Vec<Quad>> = Vec::new();
+    let mut quadtree_leaves = vec![initial_quad];
+    loop {
+        let (to_split, mut next_leaves): (Vec<Quad>, Vec<Quad>) = quadtree_leaves
+            .iter()
+            .cloned()
+            .partition(|quad| quad.should_subdivide());
+        quadtree_leaves.retain(|quad| !quad.is_skipped());
+        frames.push(quadtree_leaves);
+        if to_split.is_empty() {
+            return frames;
+        }
+        next_leaves.extend(to_split.iter().flat_map(|quad| quad.split()));
+        quadtree_leaves = next_leaves;
+    }
+}
+
+/// Returns the leaf set after every `splits_per_frame` splits of
+/// `subdivide_to_count`, ending with the final leaves.
+pub fn frames_by_splits(
+    initial_quad: Quad,
+    target_leaves: usize,
+    splits_per_frame: usize,
+) -> Vec<Vec<Quad>> {
+    let mut frames: Vec<Vec<Quad>> = vec![vec![initial_quad.clone()]];
+    let mut splits = 0;
+    let quadtree_leaves = subdivide_to_count_with(initial_quad, target_leaves, |leaves| {
+        splits += 1;
+        if splits % splits_per_frame.max(1) == 0 {
+            frames.push(leaves.filter(|quad| !quad.is_skipped()).cloned().collect());
+        }
+    });
+    frames.push(quadtree_leaves);
+    frames
+}
+
+/// Order in which an animation reveals leaves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RevealOrder {
+    /// Refine the whole image one depth level (or batch of splits) per frame.
+    #[default]
+    Depth,
+    /// Draw the final leaves in the order subdivision produced them.
+    Queue,
+    /// Draw the final leaves along a Hilbert curve through their centers, so
+    /// the image fills in as one continuous, winding stroke.
+    Hilbert,
+}
+
+impl FromStr for RevealOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(RevealOrder::Depth),
+            "queue" => Ok(RevealOrder::Queue),
+            "hilbert" => Ok(RevealOrder::Hilbert),
+            _ => Err(format!("Unknown reveal order: {}", s)),
+        }
+    }
+}
+
+/// Bits per axis of the grid leaf centers are snapped to for `hilbert_index`.
+const HILBERT_ORDER: u32 = 16;
+
+/// Returns `frame_count` frames that each draw a growing prefix of the final
+/// leaves, sorted by `order`, ending with all of them. `RevealOrder::Depth`
+/// draws shallow leaves before deep ones.
+pub fn frames_by_reveal(
+    quadtree_leaves: &[Quad],
+    order: RevealOrder,
+    frame_count: usize,
+) -> Vec<Vec<Quad>> {
+    let mut leaves = quadtree_leaves.to_vec();
+    match order {
+        RevealOrder::Depth => leaves.sort_by_key(|leaf| leaf.cur_depth),
+        RevealOrder::Queue => {}
+        RevealOrder::Hilbert => sort_by_hilbert(&mut leaves),
+    }
+    let frame_count = frame_count.max(1);
+    (1..=frame_count)
+        .map(|frame| leaves[..leaves.len() * frame / frame_count].to_vec())
+        .collect()
+}
+
+/// Sorts leaves by the Hilbert index of their centers. The bounding box of
+/// the leaves is stretched onto a square grid on each axis separately, so
+/// any image size uses the whole curve.
+fn sort_by_hilbert(leaves: &mut [Quad]) {
+    let min_x = leaves.iter().map(|leaf| leaf.x).min().unwrap_or(0);
+    let min_y = leaves.iter().map(|leaf| leaf.y).min().unwrap_or(0);
+    let max_x = leaves
+        .iter()
+        .map(|leaf| leaf.x + leaf.width)
+        .max()
+        .unwrap_or(0);
+    let max_y = leaves
+        .iter()
+        .map(|leaf| leaf.y + leaf.height)
+        .max()
+        .unwrap_or(0);
+    let grid_size = 1u64 << HILBERT_ORDER;
+    // Centers are kept in doubled coordinates to stay integral
+    let to_grid = |start: u32, size: u32, min: u32, max: u32| {
+        let doubled_center = 2 * (start - min) as u64 + size as u64;
+        let doubled_span = (2 * (max - min) as u64).max(1);
+        (doubled_center * grid_size / doubled_span).min(grid_size - 1)
+    };
+    leaves.sort_by_cached_key(|leaf| {
+        hilbert_index(
+            to_grid(leaf.x, leaf.width, min_x, max_x),
+            to_grid(leaf.y, leaf.height, min_y, max_y),
+            grid_size,
+        )
+    });
+}
+
+/// Returns the distance along the Hilbert curve filling a `grid_size` x
+/// `grid_size` grid of the cell at `(x, y)`. `grid_size` must be a power of two.
+fn hilbert_index(mut x: u64, mut y: u64, grid_size: u64) -> u64 {
+    let mut index = 0;
+    let mut s = grid_size / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the sub-curve starts and ends in the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = grid_size - 1 - x;
+                y = grid_size - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+/// Encodes each leaf set as one frame of a looping animated GIF, rendered the
+/// same way `generate_image` renders the final leaves.
+pub fn export_animation(
+    frames: &[Vec<Quad>],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+    file_path: &str,
+    fps: u32,
+) -> Result<(), QuadError> {
+    let writer = BufWriter::new(File::create(file_path)?);
+    let mut encoder = GifEncoder::new(writer);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, fps.max(1));
+    for leaves in frames {
+        let frame_image = render_to_image(leaves, image_width, image_height, config);
+        encoder.encode_frame(Frame::from_parts(frame_image, 0, 0, delay))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+use color::{Deg, Hsv, Rgb, ToRgb};
+use image::Rgba;
+
+use crate::config::{ChannelFocus, ColorMetric};
+
+/// Parses a `#RRGGBB` (or `RRGGBB`) hex string into an opaque color.
+pub fn parse_hex_color(hex: &str) -> Result<Rgba<u8>, String> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(format!("Invalid hex color: {}", hex));
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("Invalid hex color: {}", hex))
+    };
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+/// Parses a palette with one `#RRGGBB` color per line. Blank lines are skipped.
+pub fn parse_palette(text: &str) -> Result<Vec<Rgba<u8>>, String> {
+    let palette = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(parse_hex_color)
+        .collect::<Result<Vec<_>, _>>()?;
+    if palette.is_empty() {
+        return Err(String::from("Palette has no colors"));
+    }
+    Ok(palette)
+}
+
+/// Maps `depth` from 0 to `max_depth` onto a fully saturated gradient from blue
+/// to red.
+pub fn depth_heatmap_color(depth: u32, max_depth: u32) -> Rgba<u8> {
+    let t = depth.min(max_depth) as f32 / max_depth.max(1) as f32;
+    let hsv: Hsv<f32> = Hsv::new(Deg(240.0 * (1.0 - t)), 1.0, 1.0);
+    let rgb: Rgb<u8> = hsv.to_rgb();
+    Rgba([rgb.r, rgb.g, rgb.b, 255])
+}
+
+/// Offsets each RGB channel by a pseudo-random amount in `[-jitter, jitter]`,
+/// clamped to 0..255. The amounts depend only on `seed` and the position, so
+/// the same leaf gets the same jitter whatever order leaves are drawn in.
+pub fn jitter_color(color: Rgba<u8>, jitter: u8, seed: u64, x: u32, y: u32) -> Rgba<u8> {
+    // FNV-1a to mix seed and position, then SplitMix64 for each channel
+    let mut state: u64 = 0xcbf29ce484222325;
+    for byte in seed
+        .to_le_bytes()
+        .into_iter()
+        .chain(x.to_le_bytes())
+        .chain(y.to_le_bytes())
+    {
+        state ^= byte as u64;
+        state = state.wrapping_mul(0x100000001b3);
+    }
+    let mut next_random = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let span = 2 * jitter as u64 + 1;
+    let [r, g, b, a] = color.0;
+    let [r, g, b] = [r, g, b].map(|channel| {
+        let offset = (next_random() % span) as i32 - jitter as i32;
+        (channel as i32 + offset).clamp(0, 255) as u8
+    });
+    Rgba([r, g, b, a])
+}
+
+/// Multiplies a color's HSL saturation by `saturation` and scales its lightness
+/// around 0.5 by `contrast`, clamping both to their valid range. Alpha is kept.
+pub fn adjust_color(color: Rgba<u8>, saturation: f64, contrast: f64) -> Rgba<u8> {
+    let [r, g, b, a] = color.0;
+    let (hue, s, l) = rgb_to_hsl([r, g, b].map(|channel| channel as f64 / 255.0));
+    let s = (s * saturation).clamp(0.0, 1.0);
+    let l = ((l - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+    let [r, g, b] = hsl_to_rgb(hue, s, l).map(|channel| (channel * 255.0).round() as u8);
+    Rgba([r, g, b, a])
+}
+
+/// Converts RGB channels from 0 to 1 into a hue in degrees, and a saturation
+/// and lightness from 0 to 1.
+fn rgb_to_hsl([r, g, b]: [f64; 3]) -> (f64, f64, f64) {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let chroma = max - min;
+    let lightness = (max + min) / 2.0;
+    if chroma == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    (hue, saturation, lightness)
+}
+
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> [f64; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let second = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, second, 0.0],
+        1 => [second, chroma, 0.0],
+        2 => [0.0, chroma, second],
+        3 => [0.0, second, chroma],
+        4 => [second, 0.0, chroma],
+        _ => [chroma, 0.0, second],
+    };
+    let offset = lightness - chroma / 2.0;
+    [r + offset, g + offset, b + offset].map(|channel| channel.clamp(0.0, 1.0))
+}
+
+/// Snaps each color channel to the nearest of `levels` evenly spaced values from 0 to 255.
+pub fn posterize(color: Rgba<u8>, levels: u8) -> Rgba<u8> {
+    let step = 255.0 / (levels.max(2) - 1) as f64;
+    let [r, g, b, a] = color.0;
+    let [r, g, b] = [r, g, b].map(|channel| ((channel as f64 / step).round() * step).round() as u8);
+    Rgba([r, g, b, a])
+}
+
+/// Replaces the RGB of a color with that of the nearest `palette` entry under
+/// `metric`, keeping alpha. An empty palette leaves the color unchanged.
+pub fn nearest_palette_color(
+    color: Rgba<u8>,
+    palette: &[Rgba<u8>],
+    metric: ColorMetric,
+) -> Rgba<u8> {
+    let nearest = palette.iter().min_by(|a, b| {
+        color_distance(color, **a, metric).total_cmp(&color_distance(color, **b, metric))
+    });
+    match nearest {
+        Some(&Rgba([r, g, b, _])) => Rgba([r, g, b, color.0[3]]),
+        None => color,
+    }
+}
+
+/// Distance between the RGB of two colors under `metric`, on the same scale as
+/// the metric's subdivision thresholds. Alpha is ignored.
+pub fn color_distance(a: Rgba<u8>, b: Rgba<u8>, metric: ColorMetric) -> f64 {
+    match metric {
+        ColorMetric::Rgb => {
+            (0..3)
+                .map(|channel| (a.0[channel] as f64 - b.0[channel] as f64).abs())
+                .sum::<f64>()
+                / 3.0
+        }
+        ColorMetric::WeightedRgb => weighted_rgb_distance(a, b),
+        ColorMetric::CieLab => delta_e76(srgb_to_lab(a), srgb_to_lab(b)),
+    }
+}
+
+/// Rec. 601 luma coefficients, also used as the channel weights of `weighted_rgb_distance`.
+const LUMA_WEIGHTS: [f64; 3] = [0.299, 0.587, 0.114];
+
+/// Replaces a color with its Rec. 601 luma in all three channels, keeping alpha.
+pub fn to_grayscale(color: Rgba<u8>) -> Rgba<u8> {
+    let luma = luma(color).round() as u8;
+    Rgba([luma, luma, luma, color.0[3]])
+}
+
+fn luma(color: Rgba<u8>) -> f64 {
+    let [r, g, b, _] = color.0;
+    LUMA_WEIGHTS[0] * r as f64 + LUMA_WEIGHTS[1] * g as f64 + LUMA_WEIGHTS[2] * b as f64
+}
+
+/// Converts a color to full-range Rec. 601 YCbCr, with each component in 0..255.
+pub fn rgb_to_ycbcr(color: Rgba<u8>) -> [f64; 3] {
+    let [r, g, b] = [color.0[0], color.0[1], color.0[2]].map(|channel| channel as f64);
+    [
+        luma(color),
+        128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b,
+    ]
+}
+
+/// Returns the HSV hue in degrees and saturation from 0 to 1 of a color.
+fn hue_saturation(color: Rgba<u8>) -> (f64, f64) {
+    let [r, g, b] = [color.0[0], color.0[1], color.0[2]].map(|channel| channel as f64);
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    if chroma == 0.0 {
+        return (0.0, 0.0);
+    }
+    let hue = if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    (hue, chroma / max)
+}
+
+/// Distance from 0 to 255 between a pixel and a reference color, measured
+/// only on the part of the color picked by `focus`. `ChannelFocus::All` falls
+/// back to the mean absolute RGB difference.
+pub fn focus_distance(pixel: Rgba<u8>, reference: Rgba<u8>, focus: ChannelFocus) -> f64 {
+    match focus {
+        ChannelFocus::All => {
+            (0..3)
+                .map(|channel| (pixel.0[channel] as f64 - reference.0[channel] as f64).abs())
+                .sum::<f64>()
+                / 3.0
+        }
+        ChannelFocus::Hue => {
+            let (pixel_hue, pixel_saturation) = hue_saturation(pixel);
+            let (reference_hue, _) = hue_saturation(reference);
+            let difference = (pixel_hue - reference_hue).abs();
+            let difference = difference.min(360.0 - difference);
+            difference / 180.0 * 255.0 * pixel_saturation
+        }
+        ChannelFocus::Saturation => {
+            (hue_saturation(pixel).1 - hue_saturation(reference).1).abs() * 255.0
+        }
+        ChannelFocus::Luma => (luma(pixel) - luma(reference)).abs(),
+        ChannelFocus::Single(channel) => {
+            let channel = channel.min(3);
+            (pixel.0[channel] as f64 - reference.0[channel] as f64).abs()
+        }
+    }
+}
+
+/// D65 reference white in XYZ, scaled so Y = 1.
+const D65_WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+fn srgb_to_linear(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts an sRGB color to CIE XYZ (D65) through linear RGB.
+pub fn srgb_to_xyz(color: Rgba<u8>) -> [f64; 3] {
+    let [r, g, b] = [color.0[0], color.0[1], color.0[2]].map(srgb_to_linear);
+    [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+    ]
+}
+
+/// Converts an sRGB color to CIELAB (D65). L is in 0..100.
+pub fn srgb_to_lab(color: Rgba<u8>) -> [f64; 3] {
+    let xyz = srgb_to_xyz(color);
+    let [fx, fy, fz] = [0, 1, 2].map(|i| {
+        let t = xyz[i] / D65_WHITE[i];
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    });
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIE76 color difference between two LAB colors.
+pub fn delta_e76(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Euclidean RGB distance with each squared channel difference weighted by how
+/// sensitive the eye is to it. Ranges from 0 to 255 like a single channel.
+pub fn weighted_rgb_distance(a: Rgba<u8>, b: Rgba<u8>) -> f64 {
+    (0..3)
+        .map(|channel| {
+            let diff = a.0[channel] as f64 - b.0[channel] as f64;
+            LUMA_WEIGHTS[channel] * diff * diff
+        })
+        .sum::<f64>()
+        .sqrt()
+}
@@ -0,0 +1,806 @@
+use image::Rgba;
+use std::str::FromStr;
+
+use crate::color::{delta_e76, srgb_to_lab};
+use crate::error::QuadError;
+
+/// How the difference between two colors is measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMetric {
+    /// Mean absolute difference of the R, G and B channels, from 0 to 255.
+    /// Useful thresholds are usually between 5 and 30.
+    #[default]
+    Rgb,
+    /// Euclidean RGB distance with the squared channel differences weighted
+    /// (0.299, 0.587, 0.114), so green matters most. From 0 to 255, with useful
+    /// thresholds in a similar range to `Rgb`.
+    WeightedRgb,
+    /// CIE76 Delta E in CIELAB, which tracks perceived difference more closely.
+    /// A Delta E of about 2.3 is just noticeable; useful thresholds are usually
+    /// between 3 and 15.
+    CieLab,
+}
+
+/// How the color spread of a quad is measured for comparison with
+/// `color_threshold`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThresholdMetric {
+    /// Mean distance of the quad's pixels from its average color, measured with
+    /// `color_metric`.
+    #[default]
+    MeanDistance,
+    /// Mean per-channel variance of R, G and B, from 0 to about 16256. Being in
+    /// squared channel units, useful thresholds are roughly the square of `Rgb`
+    /// ones, usually between 50 and 1000.
+    Variance,
+    /// `Variance` over only the pixels whose Sobel gradient magnitude reaches
+    /// `edge_mask_threshold`, so quads split on the colors either side of an
+    /// edge while noise in flat areas is ignored. Quads with no edge pixels fall
+    /// back to the variance of all their pixels.
+    EdgeMaskedVariance,
+    /// Mean Sobel gradient magnitude of the luma, so quads split on texture and
+    /// edges even when their colors average out. From 0 to about 1440; useful
+    /// thresholds are usually between 20 and 80.
+    EdgeDensity,
+}
+
+/// Color space the subdivision test is carried out in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The color distance from `color_metric` against `color_threshold`.
+    #[default]
+    Rgb,
+    /// Rec. 601 YCbCr, with the variance of the luma checked against
+    /// `luma_threshold` and the mean variance of the two chroma channels against
+    /// `chroma_threshold`. A quad is split when either exceeds its threshold, so
+    /// luminance detail can be kept while chroma noise is tolerated.
+    YCbCr,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(ColorSpace::Rgb),
+            "ycbcr" => Ok(ColorSpace::YCbCr),
+            _ => Err(format!("Unknown color space: {}", s)),
+        }
+    }
+}
+
+/// Part of each pixel's color that the subdivision threshold looks at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelFocus {
+    /// The whole color, measured with `color_metric`.
+    #[default]
+    All,
+    /// HSV hue, with differences weighted by the pixel's saturation so grays
+    /// and near-grays, whose hue is meaningless, count for little.
+    Hue,
+    /// HSV saturation.
+    Saturation,
+    /// Rec. 601 luma.
+    Luma,
+    /// A single channel: 0 for red, 1 for green, 2 for blue and 3 for alpha.
+    Single(usize),
+}
+
+impl FromStr for ChannelFocus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(ChannelFocus::All),
+            "hue" => Ok(ChannelFocus::Hue),
+            "saturation" => Ok(ChannelFocus::Saturation),
+            "luma" => Ok(ChannelFocus::Luma),
+            "red" => Ok(ChannelFocus::Single(0)),
+            "green" => Ok(ChannelFocus::Single(1)),
+            "blue" => Ok(ChannelFocus::Single(2)),
+            "alpha" => Ok(ChannelFocus::Single(3)),
+            _ => Err(format!("Unknown channel focus: {}", s)),
+        }
+    }
+}
+
+/// How the pixels of a quad are combined into the single color it is drawn with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorAggregation {
+    /// Alpha-weighted mean, read in constant time from the integral image.
+    #[default]
+    Mean,
+    /// Per-channel median, which keeps a leaf straddling two distinct colors
+    /// closer to one of them instead of muddying both. It needs a scan over every
+    /// pixel of each quad, including ones that are later subdivided, so it adds
+    /// roughly one extra pass over the image per depth level. Histograms are used
+    /// instead of sorting, so the cost stays linear in the pixel count.
+    Median,
+}
+
+/// What decides the color each leaf is filled with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// The leaf's own color from the source image.
+    #[default]
+    Average,
+    /// A blue-to-red heatmap of the leaf's depth, showing where detail is spent.
+    Depth,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "average" => Ok(ColorMode::Average),
+            "depth" => Ok(ColorMode::Depth),
+            _ => Err(format!("Unknown color mode: {}", s)),
+        }
+    }
+}
+
+/// Shape each leaf is filled with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShapeMode {
+    /// Fills the whole quad.
+    #[default]
+    Rectangle,
+    /// Fills the largest circle centered in the quad, leaving the corners as background.
+    Circle,
+    /// Fills the ellipse inscribed in the quad.
+    Ellipse,
+    /// Fills the quad with its corners rounded to `radius` pixels, clamped to
+    /// half its shorter side.
+    RoundedRect { radius: u32 },
+}
+
+/// How the area of each leaf is colored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FillMode {
+    /// One flat color per leaf.
+    #[default]
+    Flat,
+    /// A bilinear gradient between the source pixels at the leaf's four corners,
+    /// which looks much smoother when there are few leaves. Only rectangles in
+    /// raster output are filled this way; other shapes and SVG stay flat.
+    CornerGradient,
+    /// The untouched source pixels of the leaf's rectangle, whatever the shape,
+    /// so only the outlines show the quadtree. SVG output stays flat.
+    Source,
+    /// Nothing, so only the outlines over the background show the quadtree, as
+    /// line art.
+    Wireframe,
+}
+
+impl FromStr for FillMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(FillMode::Flat),
+            "gradient" => Ok(FillMode::CornerGradient),
+            "source" => Ok(FillMode::Source),
+            "wireframe" => Ok(FillMode::Wireframe),
+            _ => Err(format!("Unknown fill mode: {}", s)),
+        }
+    }
+}
+
+/// Corner radius of `ShapeMode::RoundedRect` when parsed from `rounded`.
+pub const DEFAULT_CORNER_RADIUS: u32 = 4;
+
+impl FromStr for ShapeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rectangle" => Ok(ShapeMode::Rectangle),
+            "circle" => Ok(ShapeMode::Circle),
+            "ellipse" => Ok(ShapeMode::Ellipse),
+            "rounded" => Ok(ShapeMode::RoundedRect {
+                radius: DEFAULT_CORNER_RADIUS,
+            }),
+            _ => Err(format!("Unknown shape: {}", s)),
+        }
+    }
+}
+
+/// A shape drawn centered over every leaf after its fill, such as an accent
+/// circle in a lighter shade of the leaf's color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapeLayer {
+    pub shape: ShapeMode,
+    /// Size of the layer relative to the leaf, from 0 to 1.
+    pub scale: f64,
+    pub color: LayerColor,
+}
+
+/// How the color of a `ShapeLayer` is derived from the leaf it is drawn on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerColor {
+    /// The same color on every leaf.
+    Fixed(Rgba<u8>),
+    /// The leaf's fill color mixed towards white by a factor from 0 to 1.
+    Lighten(f64),
+    /// The leaf's fill color mixed towards black by a factor from 0 to 1.
+    Darken(f64),
+}
+
+impl LayerColor {
+    /// Returns the layer color for a leaf filled with `fill`. Mixed colors keep
+    /// the fill's alpha.
+    pub fn derive(self, fill: Rgba<u8>) -> Rgba<u8> {
+        let mix = |target: f64, amount: f64| {
+            let [r, g, b, a] = fill.0;
+            let amount = amount.clamp(0.0, 1.0);
+            let [r, g, b] = [r, g, b]
+                .map(|channel| (channel as f64 + (target - channel as f64) * amount).round() as u8);
+            Rgba([r, g, b, a])
+        };
+        match self {
+            LayerColor::Fixed(color) => color,
+            LayerColor::Lighten(amount) => mix(255.0, amount),
+            LayerColor::Darken(amount) => mix(0.0, amount),
+        }
+    }
+}
+
+/// How a quad is split when it is subdivided.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Always split into four quadrants.
+    #[default]
+    Quad,
+    /// Split quads at least twice as long as they are wide in two along their
+    /// longer axis, and the rest into four quadrants, so leaves stay closer to square.
+    Adaptive,
+}
+
+impl FromStr for SplitStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quad" => Ok(SplitStrategy::Quad),
+            "adaptive" => Ok(SplitStrategy::Adaptive),
+            _ => Err(format!("Unknown split strategy: {}", s)),
+        }
+    }
+}
+
+/// Which side of an odd-sized split gets the extra pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitRounding {
+    /// The top and left children, so the split point is rounded up. Over many
+    /// levels this shifts detail slightly toward the top-left.
+    #[default]
+    Ceil,
+    /// The bottom and right children.
+    Floor,
+    /// The top and left children on even depths and the bottom and right ones on
+    /// odd depths, so the bias averages out.
+    Alternate,
+}
+
+impl FromStr for SplitRounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ceil" => Ok(SplitRounding::Ceil),
+            "floor" => Ok(SplitRounding::Floor),
+            "alternate" => Ok(SplitRounding::Alternate),
+            _ => Err(format!("Unknown split rounding: {}", s)),
+        }
+    }
+}
+
+/// Which parts of the output are reflections of the subdivided part, for
+/// symmetric art from any source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    /// The left half is subdivided and reflected onto the right half.
+    Horizontal,
+    /// The top half is subdivided and reflected onto the bottom half.
+    Vertical,
+    /// The top-left quadrant is subdivided and reflected onto the other three.
+    Quad,
+}
+
+impl Symmetry {
+    /// Whether the right half is a reflection of the left one.
+    pub fn mirrors_horizontally(self) -> bool {
+        matches!(self, Symmetry::Horizontal | Symmetry::Quad)
+    }
+
+    /// Whether the bottom half is a reflection of the top one.
+    pub fn mirrors_vertically(self) -> bool {
+        matches!(self, Symmetry::Vertical | Symmetry::Quad)
+    }
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "horizontal" => Ok(Symmetry::Horizontal),
+            "vertical" => Ok(Symmetry::Vertical),
+            "quad" => Ok(Symmetry::Quad),
+            _ => Err(format!("Unknown symmetry: {}", s)),
+        }
+    }
+}
+
+/// Sub-rectangle of the source image to turn into art.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CropRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for CropRegion {
+    type Err = String;
+
+    /// Parses `x,y,width,height`. The width and height must be non-zero.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid crop region: {}", s))?;
+        match values[..] {
+            [_, _, width, height] if width == 0 || height == 0 => {
+                Err(format!("Crop region must not be empty: {}", s))
+            }
+            [x, y, width, height] => Ok(CropRegion {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(format!("Invalid crop region: {}", s)),
+        }
+    }
+}
+
+/// Smallest Delta E `QuadConfig::check_palette_contrast` accepts between two
+/// palette colors.
+pub const MIN_PALETTE_DELTA_E: f64 = 10.0;
+
+/// Smallest spread of CIELAB lightness, from 0 to 100, that
+/// `QuadConfig::check_palette_contrast` accepts across a palette.
+pub const MIN_PALETTE_LIGHTNESS_RANGE: f64 = 30.0;
+
+/// Parameters that control how the image is subdivided, colored and drawn.
+/// Where the result is saved and how it is encoded is up to `OutputOptions`.
+#[derive(Clone, Debug)]
+pub struct QuadConfig {
+    /// Deepest level a quad may be subdivided to.
+    pub max_depth: u32,
+    /// Color spread above which a quad is subdivided. Its range depends on
+    /// `threshold_metric` and `color_metric`.
+    pub color_threshold: f64,
+    /// Factor the color threshold is multiplied by at each level of depth, so
+    /// values below 1 make deeper quads split more readily.
+    pub threshold_decay: f64,
+    /// Color distance, in the same units as `color_threshold`, that is put down
+    /// to noise and subtracted before the comparison, so sensor noise alone does
+    /// not split otherwise flat regions.
+    pub noise_floor: f64,
+    /// Subdivides every quad down to `max_depth` (or the size threshold) whatever
+    /// its color, giving a regular grid of averaged cells.
+    pub uniform_grid: bool,
+    /// Metric used to measure color distance.
+    pub color_metric: ColorMetric,
+    /// How a quad's color spread is measured before it is compared with
+    /// `color_threshold`.
+    pub threshold_metric: ThresholdMetric,
+    /// Sobel gradient magnitude of the luma, from 0 to about 1440, at which a
+    /// pixel counts as an edge for `ThresholdMetric::EdgeMaskedVariance`. A
+    /// clean step of 25 in luma reaches 100.
+    pub edge_mask_threshold: f64,
+    /// Restricts the color distance to one part of the color. Anything other
+    /// than `ChannelFocus::All` replaces `color_metric` with the mean absolute
+    /// difference of that part, scaled from 0 to 255 like `ColorMetric::Rgb`.
+    pub channel_focus: ChannelFocus,
+    /// Color space the subdivision test uses. `ColorSpace::YCbCr` replaces
+    /// `color_threshold` with `luma_threshold` and `chroma_threshold`.
+    pub color_space: ColorSpace,
+    /// Luma variance above which a quad is subdivided in `ColorSpace::YCbCr`, in
+    /// squared channel units like `ThresholdMetric::Variance`.
+    pub luma_threshold: f64,
+    /// Mean Cb and Cr variance above which a quad is subdivided in
+    /// `ColorSpace::YCbCr`. It is usually set higher than `luma_threshold`, as the
+    /// eye is less sensitive to chroma detail.
+    pub chroma_threshold: f64,
+    /// How each quad's pixels are combined into its color.
+    pub color_aggregation: ColorAggregation,
+    /// Quads whose width or height is at most this many pixels are not subdivided.
+    pub size_threshold: u32,
+    /// Scales the size threshold up at shallow depths so only deep quads can become tiny.
+    pub size_threshold_by_depth: bool,
+    /// Most leaves threshold-based subdivision may produce, or `None` for no
+    /// limit. Once splitting a quad would exceed it, the remaining quads are
+    /// kept as leaves unsplit.
+    pub max_leaves: Option<usize>,
+    /// Power the area of a quad is raised to in its `subdivide_to_count`
+    /// priority, which is its color distance times that. 1 weighs error by
+    /// size, while 0 ignores size and splits the quad with the worst color
+    /// error first.
+    pub priority_area_exponent: f64,
+    /// Drops leaves whose average alpha is below `alpha_threshold` instead of
+    /// drawing them, so the art stays on the subject of a transparent image.
+    pub skip_transparent: bool,
+    /// Average alpha below which `skip_transparent` drops a leaf. The default of
+    /// 1 only drops fully transparent leaves.
+    pub alpha_threshold: u8,
+    /// What decides the color each leaf is filled with.
+    pub color_mode: ColorMode,
+    /// Multiplies the HSL saturation of each leaf's color, so values above 1
+    /// counter the washed-out look averaging can give. 1 leaves it unchanged.
+    pub saturation_boost: f64,
+    /// Scales each leaf's HSL lightness away from (above 1) or toward (below 1)
+    /// mid-gray. 1 leaves it unchanged.
+    pub contrast: f64,
+    /// Offsets each channel of a leaf's color by up to this much for a painterly
+    /// look. 0 turns it off.
+    pub color_jitter: u8,
+    /// Seed for `color_jitter`, so the same seed gives the same output.
+    pub seed: u64,
+    /// Quantizes each channel of a leaf's color to this many evenly spaced levels.
+    pub posterize_levels: Option<u8>,
+    /// Fills each leaf with the Rec. 601 luma of its color instead of the color itself.
+    pub grayscale: bool,
+    /// Colors each leaf is snapped to, after all other color post-processing. The
+    /// nearest entry is picked with `color_metric`, and the leaf keeps its alpha.
+    pub palette: Option<Vec<Rgba<u8>>>,
+    /// Diffuses the error of snapping to `palette` onto the neighboring leaves to
+    /// the right and below, Floyd-Steinberg style, so gradients come out as a mix
+    /// of palette colors instead of bands. Leaves are then always filled flat.
+    pub dither: bool,
+    /// How quads are split when they are subdivided.
+    pub split_strategy: SplitStrategy,
+    /// Which children get the extra pixel when a quad with an odd side is split.
+    pub split_rounding: SplitRounding,
+    /// How much a quad's contrast with the ring of pixels just outside it adds to its
+    /// color distance, so small distinct spots split even when they are uniform inside.
+    pub surround_contrast_weight: f64,
+    /// Inclusive min/max RGB boxes. When any are set, only quads whose average color
+    /// falls in one of them may subdivide all the way to `max_depth`; the rest stop
+    /// at half of it.
+    pub detail_color_ranges: Vec<(Rgba<u8>, Rgba<u8>)>,
+    /// Region of the source the initial quad covers. Leaves keep source coordinates
+    /// and the renderers shift them so the region's corner is the output origin.
+    pub crop: Option<CropRegion>,
+    /// Reflects the leaves of one half or quadrant of the output onto the rest.
+    /// The initial quad should then cover only `subdivided_size` of the output,
+    /// starting at its top-left corner.
+    pub symmetry: Option<Symmetry>,
+    /// Draws outline segments that lie on the canvas edge. Turning this off leaves
+    /// no frame around the image, which lets the output tile seamlessly.
+    pub draw_border_edges: bool,
+    /// Width in pixels over which leaf fills blend toward the neighboring leaf's
+    /// color at their shared boundary, softening the block edges.
+    pub edge_feather: u32,
+    /// Color of the outline drawn around each leaf, or `None` for borderless output.
+    pub outline_color: Option<Rgba<u8>>,
+    /// Width of the outline in pixels.
+    pub outline_thickness: u32,
+    /// Draws the outlines of shallow leaves thicker, one pixel per level above
+    /// `max_depth`, so the large structural divisions stand out.
+    pub depth_weighted_outline: bool,
+    /// Color the output starts out as, which shows wherever leaves don't cover it,
+    /// such as between circles and ellipses.
+    pub background_color: Rgba<u8>,
+    /// Shape drawn for each leaf.
+    pub shape_mode: ShapeMode,
+    /// Shapes drawn over each leaf after it is filled, in order. They are left
+    /// out of `FillMode::Source` and `FillMode::Wireframe` output.
+    pub shape_layers: Vec<ShapeLayer>,
+    /// How each leaf's shape is colored.
+    pub fill_mode: FillMode,
+    /// Bits per channel of raster output, 8 or 16. 16-bit output needs a format
+    /// that supports it, such as PNG, and keeps the precision of 16-bit sources.
+    pub output_bit_depth: u8,
+}
+
+impl Default for QuadConfig {
+    fn default() -> Self {
+        QuadConfig {
+            max_depth: 7,
+            color_threshold: 10.0,
+            threshold_decay: 1.0,
+            noise_floor: 0.0,
+            uniform_grid: false,
+            color_metric: ColorMetric::Rgb,
+            threshold_metric: ThresholdMetric::MeanDistance,
+            edge_mask_threshold: 100.0,
+            channel_focus: ChannelFocus::All,
+            color_space: ColorSpace::Rgb,
+            luma_threshold: 100.0,
+            chroma_threshold: 400.0,
+            color_aggregation: ColorAggregation::Mean,
+            size_threshold: 5,
+            size_threshold_by_depth: false,
+            max_leaves: None,
+            priority_area_exponent: 1.0,
+            skip_transparent: false,
+            alpha_threshold: 1,
+            color_mode: ColorMode::Average,
+            saturation_boost: 1.0,
+            contrast: 1.0,
+            color_jitter: 0,
+            seed: 0,
+            posterize_levels: None,
+            grayscale: false,
+            palette: None,
+            dither: false,
+            split_strategy: SplitStrategy::Quad,
+            split_rounding: SplitRounding::Ceil,
+            surround_contrast_weight: 0.0,
+            detail_color_ranges: Vec::new(),
+            crop: None,
+            symmetry: None,
+            draw_border_edges: true,
+            edge_feather: 0,
+            outline_color: Some(Rgba([0, 0, 0, 255])),
+            outline_thickness: 1,
+            depth_weighted_outline: false,
+            background_color: Rgba([0, 0, 0, 0]),
+            shape_mode: ShapeMode::Rectangle,
+            shape_layers: Vec::new(),
+            fill_mode: FillMode::Flat,
+            output_bit_depth: 8,
+        }
+    }
+}
+
+/// Where `generate_image` saves the rendered art and how it is encoded.
+#[derive(Clone, Debug)]
+pub struct OutputOptions {
+    /// Path the rendered art is saved to. The extension picks the format.
+    pub output_file: String,
+    /// Quality from 1 to 100 of JPEG output. Lower values give smaller files with
+    /// more artifacts.
+    pub jpeg_quality: u8,
+    /// Quality from 0 to 100 of lossy WebP output, or `None` for lossless
+    /// WebP. Lower values give smaller files with more artifacts.
+    pub webp_quality: Option<u8>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            output_file: String::from("output.png"),
+            jpeg_quality: 75,
+            webp_quality: None,
+        }
+    }
+}
+
+impl QuadConfig {
+    pub fn builder() -> QuadConfigBuilder {
+        QuadConfigBuilder::default()
+    }
+
+    /// Returns the size threshold that applies to a quad at `depth`.
+    ///
+    /// With `size_threshold_by_depth` the threshold grows with the logarithm of the
+    /// remaining depth, so it is `size_threshold` at `max_depth` and several times
+    /// larger near the root.
+    pub fn effective_size_threshold(&self, depth: u32) -> u32 {
+        if !self.size_threshold_by_depth {
+            return self.size_threshold;
+        }
+        let remaining_depth = self.max_depth.saturating_sub(depth) as f64;
+        (self.size_threshold as f64 * (1.0 + (remaining_depth + 1.0).log2())).round() as u32
+    }
+
+    /// Returns the outline width of a leaf at `depth`. With
+    /// `depth_weighted_outline` it is `outline_thickness` at `max_depth` and
+    /// grows by one pixel per level toward the root.
+    pub fn effective_outline_thickness(&self, depth: u32) -> u32 {
+        if !self.depth_weighted_outline {
+            return self.outline_thickness;
+        }
+        (self.outline_thickness + self.max_depth)
+            .saturating_sub(depth)
+            .max(1)
+    }
+
+    /// Checks that `palette`, if set, can still tell regions of the image apart:
+    /// it needs at least two colors, every pair at least
+    /// `MIN_PALETTE_DELTA_E` apart, and lightnesses spanning at least
+    /// `MIN_PALETTE_LIGHTNESS_RANGE` so both shadows and highlights have a
+    /// color to snap to.
+    pub fn check_palette_contrast(&self) -> Result<(), QuadError> {
+        let Some(palette) = &self.palette else {
+            return Ok(());
+        };
+        if palette.len() < 2 {
+            return Err(QuadError::LowContrastPalette(String::from(
+                "it needs at least two colors",
+            )));
+        }
+        let labs: Vec<[f64; 3]> = palette.iter().map(|color| srgb_to_lab(*color)).collect();
+        for (i, a) in labs.iter().enumerate() {
+            for (j, b) in labs.iter().enumerate().skip(i + 1) {
+                let delta_e = delta_e76(*a, *b);
+                if delta_e < MIN_PALETTE_DELTA_E {
+                    return Err(QuadError::LowContrastPalette(format!(
+                        "colors {} and {} are only {:.1} Delta E apart",
+                        i + 1,
+                        j + 1,
+                        delta_e
+                    )));
+                }
+            }
+        }
+        let lightnesses = labs.iter().map(|lab| lab[0]);
+        let range =
+            lightnesses.clone().fold(f64::MIN, f64::max) - lightnesses.fold(f64::MAX, f64::min);
+        if range < MIN_PALETTE_LIGHTNESS_RANGE {
+            return Err(QuadError::LowContrastPalette(format!(
+                "its lightness only spans {:.1} of 100",
+                range
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether leaf colors go through `dither_leaves` when rendering. Depth
+    /// heatmaps, source fills and wireframes have no palette colors to dither.
+    pub fn dithers_palette(&self) -> bool {
+        self.dither
+            && self.palette.is_some()
+            && self.color_mode == ColorMode::Average
+            && matches!(self.fill_mode, FillMode::Flat | FillMode::CornerGradient)
+    }
+
+    /// Returns the source coordinates that map to the output origin.
+    pub fn crop_origin(&self) -> (u32, u32) {
+        self.crop.map_or((0, 0), |crop| (crop.x, crop.y))
+    }
+
+    /// Returns the size of the part of a `width` x `height` output that is
+    /// subdivided, which is all of it unless `symmetry` fills the rest with
+    /// reflections. Odd sizes round up, so the middle row or column is shared.
+    pub fn subdivided_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.symmetry {
+            None => (width, height),
+            Some(symmetry) => (
+                if symmetry.mirrors_horizontally() {
+                    width.div_ceil(2)
+                } else {
+                    width
+                },
+                if symmetry.mirrors_vertically() {
+                    height.div_ceil(2)
+                } else {
+                    height
+                },
+            ),
+        }
+    }
+
+    /// Returns the depth a quad with the given average color may subdivide to.
+    pub fn effective_max_depth(&self, color: Rgba<u8>) -> u32 {
+        let in_detail_range = self.detail_color_ranges.iter().any(|(min, max)| {
+            (0..3).all(|channel| {
+                min.0[channel] <= color.0[channel] && color.0[channel] <= max.0[channel]
+            })
+        });
+        if self.detail_color_ranges.is_empty() || in_detail_range {
+            self.max_depth
+        } else {
+            self.max_depth / 2
+        }
+    }
+}
+
+/// Chainable builder for `QuadConfig`, e.g.
+/// `QuadConfig::builder().max_depth(7).color_threshold(10.0).build()`.
+/// Anything not set keeps its default.
+#[derive(Clone, Debug, Default)]
+pub struct QuadConfigBuilder {
+    config: QuadConfig,
+}
+
+macro_rules! builder_setters {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("Sets [`QuadConfig::", stringify!($field), "`].")]
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.config.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+impl QuadConfigBuilder {
+    builder_setters! {
+        max_depth: u32,
+        color_threshold: f64,
+        threshold_decay: f64,
+        noise_floor: f64,
+        uniform_grid: bool,
+        color_metric: ColorMetric,
+        threshold_metric: ThresholdMetric,
+        edge_mask_threshold: f64,
+        channel_focus: ChannelFocus,
+        color_space: ColorSpace,
+        luma_threshold: f64,
+        chroma_threshold: f64,
+        color_aggregation: ColorAggregation,
+        size_threshold: u32,
+        size_threshold_by_depth: bool,
+        max_leaves: Option<usize>,
+        priority_area_exponent: f64,
+        skip_transparent: bool,
+        alpha_threshold: u8,
+        color_mode: ColorMode,
+        saturation_boost: f64,
+        contrast: f64,
+        color_jitter: u8,
+        seed: u64,
+        posterize_levels: Option<u8>,
+        grayscale: bool,
+        palette: Option<Vec<Rgba<u8>>>,
+        dither: bool,
+        split_strategy: SplitStrategy,
+        split_rounding: SplitRounding,
+        surround_contrast_weight: f64,
+        detail_color_ranges: Vec<(Rgba<u8>, Rgba<u8>)>,
+        crop: Option<CropRegion>,
+        symmetry: Option<Symmetry>,
+        draw_border_edges: bool,
+        edge_feather: u32,
+        outline_color: Option<Rgba<u8>>,
+        outline_thickness: u32,
+        depth_weighted_outline: bool,
+        background_color: Rgba<u8>,
+        shape_mode: ShapeMode,
+        shape_layers: Vec<ShapeLayer>,
+        fill_mode: FillMode,
+        output_bit_depth: u8,
+    }
+
+    pub fn build(self) -> QuadConfig {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn near_identical_palette_colors_are_rejected() {
+        let config = |palette: &[[u8; 3]]| QuadConfig {
+            palette: Some(
+                palette
+                    .iter()
+                    .map(|&[r, g, b]| Rgba([r, g, b, 255]))
+                    .collect(),
+            ),
+            ..QuadConfig::default()
+        };
+        let close = config(&[[120, 60, 60], [122, 61, 60]]);
+        assert!(matches!(
+            close.check_palette_contrast(),
+            Err(QuadError::LowContrastPalette(_))
+        ));
+        // Distinct hues at about the same lightness still lack tonal range
+        let flat = config(&[[200, 80, 80], [80, 160, 80]]);
+        assert!(flat.check_palette_contrast().is_err());
+
+        let separated = config(&[[20, 20, 40], [200, 60, 50], [250, 240, 200]]);
+        assert!(separated.check_palette_contrast().is_ok());
+        assert!(QuadConfig::default().check_palette_contrast().is_ok());
+    }
+}
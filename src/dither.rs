@@ -0,0 +1,124 @@
+use image::Rgba;
+
+use crate::color::nearest_palette_color;
+use crate::config::QuadConfig;
+use crate::lookup::LeafIndex;
+use crate::quad::{Quad, QuadView};
+
+/// Share of a leaf's snapping error passed to the leaves along its right edge.
+const RIGHT_ERROR_SHARE: f64 = 7.0 / 16.0;
+/// Share of a leaf's snapping error passed to the leaves along its bottom edge,
+/// which is what Floyd-Steinberg sends to the three pixels below.
+const BOTTOM_ERROR_SHARE: f64 = 9.0 / 16.0;
+
+/// Snaps every leaf to `config.palette` with error diffusion and returns views
+/// whose `color` is the final fill color, without corner colors. Leaves are
+/// visited top to bottom, then left to right. Each one adds the error it has
+/// received to its post-processed color, snaps the sum and passes what is left
+/// to the neighbors it shares its right and bottom edges with that are not
+/// snapped yet, in proportion to the length of edge they share. Render them
+/// with `without_color_processing(config)` so the colors are not processed
+/// twice.
+pub fn dither_leaves(quadtree_leaves: &[Quad], config: &QuadConfig) -> Vec<QuadView> {
+    let palette = config.palette.as_deref().unwrap_or_default();
+    let index = LeafIndex::new(quadtree_leaves);
+    let mut order: Vec<usize> = (0..quadtree_leaves.len()).collect();
+    order.sort_by_key(|&leaf| (quadtree_leaves[leaf].y, quadtree_leaves[leaf].x));
+
+    let mut views: Vec<QuadView> = quadtree_leaves.iter().map(Quad::view).collect();
+    let mut errors = vec![[0.0; 3]; views.len()];
+    let mut snapped = vec![false; views.len()];
+    for leaf in order {
+        let view = &mut views[leaf];
+        let adjusted = view.adjust_fill(Rgba(view.color), config).0;
+        let target: [f64; 3] =
+            std::array::from_fn(|channel| adjusted[channel] as f64 + errors[leaf][channel]);
+        let wanted = Rgba([
+            target[0].round().clamp(0.0, 255.0) as u8,
+            target[1].round().clamp(0.0, 255.0) as u8,
+            target[2].round().clamp(0.0, 255.0) as u8,
+            adjusted[3],
+        ]);
+        let fill = nearest_palette_color(wanted, palette, config.color_metric);
+        view.color = fill.0;
+        view.corner_colors = None;
+        snapped[leaf] = true;
+
+        let error: [f64; 3] =
+            std::array::from_fn(|channel| target[channel] - fill.0[channel] as f64);
+        let (x, y, width, height) = quadtree_leaves[leaf].bounds();
+        let edges = [
+            (
+                RIGHT_ERROR_SHARE,
+                edge_neighbors(&index, quadtree_leaves, (x + width, y), (0, 1), height),
+            ),
+            (
+                BOTTOM_ERROR_SHARE,
+                edge_neighbors(&index, quadtree_leaves, (x, y + height), (1, 0), width),
+            ),
+        ];
+        for (share, neighbors) in edges {
+            let neighbors: Vec<(usize, u32)> = neighbors
+                .into_iter()
+                .filter(|&(neighbor, _)| !snapped[neighbor])
+                .collect();
+            let shared_length: u32 = neighbors.iter().map(|&(_, length)| length).sum();
+            for (neighbor, length) in neighbors {
+                let weight = share * length as f64 / shared_length as f64;
+                for channel in 0..3 {
+                    errors[neighbor][channel] += error[channel] * weight;
+                }
+            }
+        }
+    }
+    views
+}
+
+/// Returns the leaves touching the `length` pixels that run from `start` in
+/// `direction`, with how many of those pixels each one covers.
+fn edge_neighbors(
+    index: &LeafIndex,
+    quadtree_leaves: &[Quad],
+    (start_x, start_y): (u32, u32),
+    direction: (u32, u32),
+    length: u32,
+) -> Vec<(usize, u32)> {
+    let mut neighbors = Vec::new();
+    let mut offset = 0;
+    while offset < length {
+        let (x, y) = (
+            start_x + offset * direction.0,
+            start_y + offset * direction.1,
+        );
+        let Some(neighbor) = index.find_index(x, y) else {
+            offset += 1;
+            continue;
+        };
+        let (neighbor_x, neighbor_y, neighbor_width, neighbor_height) =
+            quadtree_leaves[neighbor].bounds();
+        let end = if direction.0 > 0 {
+            neighbor_x + neighbor_width - start_x
+        } else {
+            neighbor_y + neighbor_height - start_y
+        };
+        let end = end.min(length);
+        neighbors.push((neighbor, end - offset));
+        offset = end;
+    }
+    neighbors
+}
+
+/// Returns `config` with every color post-processing step turned off, for
+/// rendering views whose colors already went through them.
+pub fn without_color_processing(config: &QuadConfig) -> QuadConfig {
+    QuadConfig {
+        saturation_boost: 1.0,
+        contrast: 1.0,
+        color_jitter: 0,
+        posterize_levels: None,
+        grayscale: false,
+        palette: None,
+        dither: false,
+        ..config.clone()
+    }
+}
@@ -0,0 +1,82 @@
+use image::error::{EncodingError, ImageError};
+use image::ImageFormat;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why loading, rendering or saving failed.
+#[derive(Debug)]
+pub enum QuadError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The file format is not supported for reading or writing.
+    UnsupportedFormat(String),
+    /// The image or output canvas has no pixels.
+    EmptyImage,
+    /// Decoding or encoding the image data failed.
+    Encode(ImageError),
+    /// A saved quadtree could not be parsed.
+    Parse(String),
+    /// A raw pixel buffer does not hold `expected` bytes for its dimensions.
+    BufferSize { expected: usize, actual: usize },
+    /// The palette's colors are too close together to tell regions apart.
+    LowContrastPalette(String),
+}
+
+impl fmt::Display for QuadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuadError::Io(err) => write!(f, "I/O error: {}", err),
+            QuadError::UnsupportedFormat(format) => write!(f, "Unsupported format: {}", format),
+            QuadError::EmptyImage => write!(f, "Image has no pixels"),
+            QuadError::Encode(err) => write!(f, "Image encoding error: {}", err),
+            QuadError::Parse(message) => write!(f, "Invalid quadtree data: {}", message),
+            QuadError::BufferSize { expected, actual } => write!(
+                f,
+                "Pixel buffer has {} bytes, expected {}",
+                actual, expected
+            ),
+            QuadError::LowContrastPalette(message) => {
+                write!(f, "Palette lacks contrast: {}", message)
+            }
+        }
+    }
+}
+
+impl Error for QuadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QuadError::Io(err) => Some(err),
+            QuadError::Encode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for QuadError {
+    fn from(err: io::Error) -> Self {
+        QuadError::Io(err)
+    }
+}
+
+impl From<ImageError> for QuadError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => QuadError::Io(err),
+            ImageError::Unsupported(err) => QuadError::UnsupportedFormat(err.to_string()),
+            err => QuadError::Encode(err),
+        }
+    }
+}
+
+impl From<png::EncodingError> for QuadError {
+    fn from(err: png::EncodingError) -> Self {
+        match err {
+            png::EncodingError::IoError(err) => QuadError::Io(err),
+            err => QuadError::Encode(ImageError::Encoding(EncodingError::new(
+                ImageFormat::Png.into(),
+                err,
+            ))),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use image::DynamicImage;
+
+/// EXIF tag holding the orientation the camera was held in.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Reads the EXIF orientation (1 to 8) from JPEG or TIFF data, if present.
+pub fn read_orientation(bytes: &[u8]) -> Option<u16> {
+    let tiff = if bytes.starts_with(&[0xff, 0xd8]) {
+        find_jpeg_exif(bytes)?
+    } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        bytes
+    } else {
+        return None;
+    };
+    read_tiff_orientation(tiff)
+}
+
+/// Returns the TIFF structure inside a JPEG's APP1 Exif segment.
+fn find_jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() {
+        if bytes[pos] != 0xff {
+            return None;
+        }
+        let marker = bytes[pos + 1];
+        // Metadata segments all come before the start of scan
+        if marker == 0xda || marker == 0xd9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+fn read_tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+
+    // The orientation is a SHORT stored inline in one of IFD0's 12-byte entries
+    let ifd = read_u32(4)? as usize;
+    let entry_count = read_u16(ifd)? as usize;
+    (0..entry_count)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION_TAG))
+        .and_then(|entry| read_u16(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Rotates and flips an image so it displays upright given its EXIF orientation.
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quad::load_image_from_memory;
+    use image::{GenericImageView, ImageOutputFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    /// A 40x20 JPEG with a white block in its top-left corner and an APP1 Exif
+    /// segment holding `orientation`.
+    fn rotated_jpeg(orientation: u16, big_endian: bool) -> Vec<u8> {
+        let image = RgbImage::from_fn(40, 20, |x, y| {
+            if x < 10 && y < 10 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(95))
+            .unwrap();
+
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut segment = b"Exif\0\0".to_vec();
+        segment.extend(if big_endian { b"MM" } else { b"II" });
+        segment.extend(u16_bytes(42));
+        segment.extend(u32_bytes(8));
+        // One IFD entry: tag, SHORT type, count 1, inline value
+        segment.extend(u16_bytes(1));
+        segment.extend(u16_bytes(ORIENTATION_TAG));
+        segment.extend(u16_bytes(3));
+        segment.extend(u32_bytes(1));
+        segment.extend(u16_bytes(orientation));
+        segment.extend([0, 0, 0, 0, 0, 0]);
+
+        let mut bytes = vec![0xff, 0xd8, 0xff, 0xe1];
+        bytes.extend((segment.len() as u16 + 2).to_be_bytes());
+        bytes.extend(segment);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    #[test]
+    fn rotated_photo_is_turned_upright() {
+        for big_endian in [false, true] {
+            let bytes = rotated_jpeg(6, big_endian);
+            assert_eq!(read_orientation(&bytes), Some(6));
+
+            let upright = load_image_from_memory(&bytes, None, true).unwrap();
+            assert_eq!(upright.dimensions(), (20, 40));
+            // Rotating 90 degrees clockwise moves the top-left block to the top-right
+            assert!(upright.get_pixel(15, 5).0[0] > 200);
+            assert!(upright.get_pixel(5, 5).0[0] < 50);
+
+            let raw = load_image_from_memory(&bytes, None, false).unwrap();
+            assert_eq!(raw.dimensions(), (40, 20));
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_orientation_is_ignored() {
+        assert_eq!(read_orientation(&rotated_jpeg(9, false)), None);
+        assert_eq!(read_orientation(b"not an image"), None);
+        let image = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        assert_eq!(read_orientation(&png), None);
+        assert_eq!(apply_orientation(image, 8).dimensions(), (2, 3));
+    }
+}
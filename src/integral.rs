@@ -0,0 +1,302 @@
+use image::{DynamicImage, GenericImageView};
+use imageproc::gradients::sobel_gradients;
+
+/// Summed-area table over an image, so the sum of any rectangle can be read
+/// from four corner lookups instead of a pixel scan. The first three channels
+/// hold alpha-weighted RGB (`channel * alpha`) and the fourth holds alpha.
+/// Sources with 16 bits per channel are summed at full precision.
+pub struct IntegralImage {
+    width: u32,
+    height: u32,
+    /// Whether the sums are of 16-bit rather than 8-bit channel values.
+    pub sixteen_bit: bool,
+    // (width + 1) x (height + 1) running sums, with a zero row and column at the start
+    sums: Sums,
+}
+
+enum Sums {
+    /// Plain RGB sums of an opaque 8-bit source, whose alpha is 255 everywhere.
+    /// They fit in a `u32` for images of up to about 16.8 million pixels, and
+    /// take under half the memory of the weighted sums.
+    Opaque(Vec<[u32; 3]>),
+    Weighted(Vec<[u64; 4]>),
+}
+
+impl IntegralImage {
+    pub fn new(image: &DynamicImage) -> IntegralImage {
+        let (width, height) = image.dimensions();
+        let sixteen_bit = is_sixteen_bit(image);
+        let fits_u32 = 255 * width as u64 * height as u64 <= u32::MAX as u64;
+        let sums = if sixteen_bit {
+            let rgba16 = image.to_rgba16();
+            Sums::Weighted(summed_area_table(width, height, |x, y| {
+                rgba16.get_pixel(x, y).0.map(|channel| channel as u64)
+            }))
+        } else if fits_u32 && image.pixels().all(|(_, _, pixel)| pixel.0[3] == 255) {
+            Sums::Opaque(opaque_summed_area_table(image))
+        } else {
+            Sums::Weighted(summed_area_table(width, height, |x, y| {
+                image.get_pixel(x, y).0.map(|channel| channel as u64)
+            }))
+        };
+        IntegralImage {
+            width,
+            height,
+            sixteen_bit,
+            sums,
+        }
+    }
+
+    /// Builds a table of the alpha-weighted squares of the 8-bit RGB channels
+    /// (`channel² * alpha`), with alpha in the fourth channel, so the variance
+    /// of a rectangle can be read alongside its mean.
+    pub fn of_squares(image: &DynamicImage) -> IntegralImage {
+        let (width, height) = image.dimensions();
+        let sums = summed_area_table(width, height, |x, y| {
+            let [r, g, b, a] = image.get_pixel(x, y).0.map(|channel| channel as u64);
+            [r * r, g * g, b * b, a]
+        });
+        IntegralImage {
+            width,
+            height,
+            sixteen_bit: false,
+            sums: Sums::Weighted(sums),
+        }
+    }
+
+    /// Returns the per-channel sums over a rectangle, clipped to the image.
+    pub fn region_sum(&self, x: u32, y: u32, width: u32, height: u32) -> [u64; 4] {
+        let x0 = x.min(self.width) as usize;
+        let y0 = y.min(self.height) as usize;
+        let x1 = (x + width).min(self.width) as usize;
+        let y1 = (y + height).min(self.height) as usize;
+        let stride = self.width as usize + 1;
+        let corners = [
+            y1 * stride + x1,
+            y0 * stride + x0,
+            y0 * stride + x1,
+            y1 * stride + x0,
+        ];
+
+        match &self.sums {
+            Sums::Opaque(sums) => {
+                let [bottom_right, top_left, top_right, bottom_left] =
+                    corners.map(|index| sums[index]);
+                // The corner sums can add up past u32::MAX, but the region's own
+                // sum fits, so wrapping arithmetic still gets it right
+                let rgb = [0, 1, 2].map(|channel| {
+                    let sum = bottom_right[channel]
+                        .wrapping_add(top_left[channel])
+                        .wrapping_sub(top_right[channel])
+                        .wrapping_sub(bottom_left[channel]);
+                    sum as u64 * 255
+                });
+                let area = ((x1 - x0) * (y1 - y0)) as u64;
+                [rgb[0], rgb[1], rgb[2], area * 255]
+            }
+            Sums::Weighted(sums) => {
+                let [bottom_right, top_left, top_right, bottom_left] =
+                    corners.map(|index| sums[index]);
+                [0, 1, 2, 3].map(|channel| {
+                    bottom_right[channel] + top_left[channel]
+                        - top_right[channel]
+                        - bottom_left[channel]
+                })
+            }
+        }
+    }
+}
+
+/// Whether an image stores 16 bits per channel.
+pub fn is_sixteen_bit(image: &DynamicImage) -> bool {
+    matches!(
+        image,
+        DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_)
+    )
+}
+
+/// Summed-area table of the Sobel gradient magnitude of an image's luma, for
+/// reading the mean edge strength of any rectangle in constant time.
+pub struct GradientIntegral {
+    width: u32,
+    height: u32,
+    sums: Vec<u64>,
+}
+
+impl GradientIntegral {
+    pub fn new(image: &DynamicImage) -> GradientIntegral {
+        let gradients = sobel_gradients(&image.to_luma8());
+        let (width, height) = gradients.dimensions();
+        let stride = width as usize + 1;
+        let mut sums = vec![0u64; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row_sum = 0;
+            for x in 0..width {
+                let index = (y as usize + 1) * stride + x as usize + 1;
+                row_sum += gradients.get_pixel(x, y).0[0] as u64;
+                sums[index] = sums[index - stride] + row_sum;
+            }
+        }
+        GradientIntegral {
+            width,
+            height,
+            sums,
+        }
+    }
+
+    /// Returns the mean gradient magnitude over a rectangle, clipped to the image.
+    pub fn region_mean(&self, x: u32, y: u32, width: u32, height: u32) -> f64 {
+        let x0 = x.min(self.width) as usize;
+        let y0 = y.min(self.height) as usize;
+        let x1 = (x + width).min(self.width) as usize;
+        let y1 = (y + height).min(self.height) as usize;
+        let area = (x1 - x0) * (y1 - y0);
+        if area == 0 {
+            return 0.0;
+        }
+        let stride = self.width as usize + 1;
+        let sum = self.sums[y1 * stride + x1] + self.sums[y0 * stride + x0]
+            - self.sums[y0 * stride + x1]
+            - self.sums[y1 * stride + x0];
+        sum as f64 / area as f64
+    }
+}
+
+/// Sums and sums of squares of the 8-bit RGB of only the pixels whose Sobel
+/// gradient magnitude reaches a threshold, weighted by alpha, so the variance
+/// along the edges of any rectangle can be read in constant time.
+pub struct EdgeMaskedIntegral {
+    sums: IntegralImage,
+    squares: IntegralImage,
+}
+
+impl EdgeMaskedIntegral {
+    pub fn new(image: &DynamicImage, min_gradient: f64) -> EdgeMaskedIntegral {
+        let gradients = sobel_gradients(&image.to_luma8());
+        let (width, height) = image.dimensions();
+        let masked_pixel = |x: u32, y: u32| {
+            let [r, g, b, a] = image.get_pixel(x, y).0.map(|channel| channel as u64);
+            let on_edge = gradients.get_pixel(x, y).0[0] as f64 >= min_gradient;
+            [r, g, b, if on_edge { a } else { 0 }]
+        };
+        let sums = summed_area_table(width, height, masked_pixel);
+        let squares = summed_area_table(width, height, |x, y| {
+            let [r, g, b, a] = masked_pixel(x, y);
+            [r * r, g * g, b * b, a]
+        });
+        let table = |sums| IntegralImage {
+            width,
+            height,
+            sixteen_bit: false,
+            sums: Sums::Weighted(sums),
+        };
+        EdgeMaskedIntegral {
+            sums: table(sums),
+            squares: table(squares),
+        }
+    }
+
+    /// Returns the mean RGB variance of the edge pixels in a rectangle, or
+    /// `None` if it has none.
+    pub fn region_variance(&self, x: u32, y: u32, width: u32, height: u32) -> Option<f64> {
+        let [red, green, blue, total_alpha] = self.sums.region_sum(x, y, width, height);
+        if total_alpha == 0 {
+            return None;
+        }
+        let [red_squared, green_squared, blue_squared, _] =
+            self.squares.region_sum(x, y, width, height);
+        let alpha = total_alpha as f64;
+        let variance_sum: f64 = [red, green, blue]
+            .into_iter()
+            .zip([red_squared, green_squared, blue_squared])
+            .map(|(sum, squared_sum)| {
+                let mean = sum as f64 / alpha;
+                (squared_sum as f64 / alpha - mean * mean).max(0.0)
+            })
+            .sum();
+        Some(variance_sum / 3.0)
+    }
+}
+
+fn summed_area_table(
+    width: u32,
+    height: u32,
+    pixel: impl Fn(u32, u32) -> [u64; 4],
+) -> Vec<[u64; 4]> {
+    let stride = width as usize + 1;
+    let mut sums = vec![[0u64; 4]; stride * (height as usize + 1)];
+    for y in 0..height {
+        let mut row_sum = [0u64; 4];
+        for x in 0..width {
+            let [r, g, b, a] = pixel(x, y);
+            let weighted = [r * a, g * a, b * a, a];
+            let index = (y as usize + 1) * stride + x as usize + 1;
+            for channel in 0..4 {
+                row_sum[channel] += weighted[channel];
+                sums[index][channel] = sums[index - stride][channel] + row_sum[channel];
+            }
+        }
+    }
+    sums
+}
+
+fn opaque_summed_area_table(image: &DynamicImage) -> Vec<[u32; 3]> {
+    let (width, height) = image.dimensions();
+    let stride = width as usize + 1;
+    let mut sums = vec![[0u32; 3]; stride * (height as usize + 1)];
+    for y in 0..height {
+        let mut row_sum = [0u32; 3];
+        for x in 0..width {
+            let pixel = image.get_pixel(x, y).0;
+            let index = (y as usize + 1) * stride + x as usize + 1;
+            for channel in 0..3 {
+                row_sum[channel] += pixel[channel] as u32;
+                sums[index][channel] = sums[index - stride][channel] + row_sum[channel];
+            }
+        }
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn opaque_and_translucent_sources_give_the_same_sums() {
+        let pixel = |x: u32, y: u32| Rgba([(x * 9) as u8, (y * 5) as u8, (x ^ y) as u8, 255]);
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_fn(31, 17, pixel));
+        // One translucent pixel outside the compared regions forces the u64 table
+        let translucent = DynamicImage::ImageRgba8(RgbaImage::from_fn(31, 17, |x, y| {
+            if (x, y) == (30, 16) {
+                Rgba([0, 0, 0, 0])
+            } else {
+                pixel(x, y)
+            }
+        }));
+        let (opaque, translucent) = (
+            IntegralImage::new(&opaque),
+            IntegralImage::new(&translucent),
+        );
+        assert!(matches!(opaque.sums, Sums::Opaque(_)));
+        assert!(matches!(translucent.sums, Sums::Weighted(_)));
+        for (x, y, width, height) in [(0, 0, 30, 16), (3, 4, 10, 7), (29, 0, 1, 16)] {
+            let expected = (y..y + height)
+                .flat_map(|y| (x..x + width).map(move |x| pixel(x, y).0))
+                .fold([0u64; 4], |mut sums, [r, g, b, a]| {
+                    let a = a as u64;
+                    sums[0] += r as u64 * a;
+                    sums[1] += g as u64 * a;
+                    sums[2] += b as u64 * a;
+                    sums[3] += a;
+                    sums
+                });
+            assert_eq!(opaque.region_sum(x, y, width, height), expected);
+            assert_eq!(translucent.region_sum(x, y, width, height), expected);
+        }
+    }
+}
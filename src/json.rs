@@ -0,0 +1,340 @@
+use image::Rgba;
+use std::fmt::Write;
+use std::fs;
+
+use crate::config::QuadConfig;
+use crate::error::QuadError;
+use crate::quad::{Quad, QuadView};
+use crate::render::to_output_space;
+use crate::svg::hex_color;
+
+/// Saves the leaves' geometry and colors as a JSON array of objects with `x`,
+/// `y`, `width`, `height`, `color` (`[r, g, b, a]`) and `cur_depth` fields, plus
+/// `corner_colors` (four `[r, g, b, a]` arrays) for leaves that have them, so
+/// they can be re-rendered later without recomputing the quadtree.
+pub fn export_quadtree(quadtree_leaves: &[Quad], file_path: &str) -> Result<(), QuadError> {
+    let views: Vec<QuadView> = quadtree_leaves.iter().map(Quad::view).collect();
+    fs::write(file_path, quadtree_to_json(&views))?;
+    Ok(())
+}
+
+/// Loads leaves saved by `export_quadtree`. They have no source image, but can
+/// be rendered directly with `render_views_to_image`.
+pub fn import_quadtree(file_path: &str) -> Result<Vec<QuadView>, QuadError> {
+    let json = fs::read_to_string(file_path)?;
+    quadtree_from_json(&json).map_err(QuadError::Parse)
+}
+
+/// Describes the leaves for other tools as
+/// `{"width": .., "height": .., "leaves": [{"x": .., "y": .., "w": .., "h": .., "color": "#rrggbb"}]}`,
+/// with each leaf's average color. Leaves are placed in output coordinates, so
+/// with `config.crop` they are relative to the region's corner. Unlike
+/// `export_quadtree`, this schema is meant to stay stable.
+pub fn export_leaves_json(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> String {
+    let mut json = format!(
+        "{{\n  \"width\": {},\n  \"height\": {},\n  \"leaves\": [\n",
+        image_width, image_height
+    );
+    for (i, leaf) in quadtree_leaves.iter().enumerate() {
+        let leaf = to_output_space(leaf.view(), config);
+        let separator = if i + 1 < quadtree_leaves.len() {
+            ","
+        } else {
+            ""
+        };
+        writeln!(
+            json,
+            "    {{\"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}, \"color\": \"{}\"}}{}",
+            leaf.x,
+            leaf.y,
+            leaf.width,
+            leaf.height,
+            hex_color(Rgba(leaf.color)),
+            separator
+        )
+        .expect("Cannot write to string.");
+    }
+    json.push_str("  ]\n}\n");
+    json
+}
+
+/// Returns the JSON array `export_quadtree` saves.
+#[cfg(feature = "serde")]
+pub fn quadtree_to_json(quadtree_leaves: &[QuadView]) -> String {
+    serde_json::to_string_pretty(quadtree_leaves).expect("Cannot serialize quadtree.")
+}
+
+/// Returns the JSON array `export_quadtree` saves. Without the `serde` feature
+/// it is written by hand.
+#[cfg(not(feature = "serde"))]
+pub fn quadtree_to_json(quadtree_leaves: &[QuadView]) -> String {
+    let mut json = String::from("[\n");
+    for (i, leaf) in quadtree_leaves.iter().enumerate() {
+        let [r, g, b, a] = leaf.color;
+        let separator = if i + 1 < quadtree_leaves.len() {
+            ","
+        } else {
+            ""
+        };
+        write!(
+            json,
+            "  {{\"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"color\": [{}, {}, {}, {}], \"cur_depth\": {}",
+            leaf.x, leaf.y, leaf.width, leaf.height, r, g, b, a, leaf.cur_depth
+        )
+        .expect("Cannot write to string.");
+        if let Some(corner_colors) = leaf.corner_colors {
+            let corners: Vec<String> = corner_colors
+                .iter()
+                .map(|[r, g, b, a]| format!("[{}, {}, {}, {}]", r, g, b, a))
+                .collect();
+            write!(json, ", \"corner_colors\": [{}]", corners.join(", "))
+                .expect("Cannot write to string.");
+        }
+        writeln!(json, "}}{}", separator).expect("Cannot write to string.");
+    }
+    json.push(']');
+    json
+}
+
+/// Parses the array written by `quadtree_to_json`.
+#[cfg(feature = "serde")]
+pub fn quadtree_from_json(json: &str) -> Result<Vec<QuadView>, String> {
+    serde_json::from_str(json).map_err(|err| err.to_string())
+}
+
+/// Parses the array written by `quadtree_to_json`. Without the `serde` feature
+/// only the subset of JSON it produces is accepted: objects whose values are
+/// integers or integer arrays.
+#[cfg(not(feature = "serde"))]
+pub fn quadtree_from_json(json: &str) -> Result<Vec<QuadView>, String> {
+    let mut parser = Parser {
+        chars: json.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+    let mut quadtree_leaves = Vec::new();
+    parser.expect('[')?;
+    if !parser.consume(']') {
+        loop {
+            quadtree_leaves.push(parser.quad_view()?);
+            if parser.consume(']') {
+                break;
+            }
+            parser.expect(',')?;
+        }
+    }
+    if parser.pos != parser.chars.len() {
+        return Err(format!("Unexpected data at offset {}", parser.pos));
+    }
+    Ok(quadtree_leaves)
+}
+
+#[cfg(not(feature = "serde"))]
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+#[cfg(not(feature = "serde"))]
+impl Parser {
+    fn consume(&mut self, expected: char) -> bool {
+        if self.chars.get(self.pos) == Some(&expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        if self.consume(expected) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at offset {}", expected, self.pos))
+        }
+    }
+
+    fn quad_view(&mut self) -> Result<QuadView, String> {
+        let mut fields: [Option<u32>; 5] = [None; 5];
+        let mut color: Option<[u8; 4]> = None;
+        let mut corner_colors: Option<[[u8; 4]; 4]> = None;
+        self.expect('{')?;
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            match key.as_str() {
+                "x" => fields[0] = Some(self.number()?),
+                "y" => fields[1] = Some(self.number()?),
+                "width" => fields[2] = Some(self.number()?),
+                "height" => fields[3] = Some(self.number()?),
+                "cur_depth" => fields[4] = Some(self.number()?),
+                "color" => color = Some(self.color()?),
+                "corner_colors" => corner_colors = Some(self.corner_colors()?),
+                _ => return Err(format!("Unknown field: {}", key)),
+            }
+            if self.consume('}') {
+                break;
+            }
+            self.expect(',')?;
+        }
+
+        let missing = |name: &str| format!("Missing field: {}", name);
+        Ok(QuadView {
+            x: fields[0].ok_or_else(|| missing("x"))?,
+            y: fields[1].ok_or_else(|| missing("y"))?,
+            width: fields[2].ok_or_else(|| missing("width"))?,
+            height: fields[3].ok_or_else(|| missing("height"))?,
+            color: color.ok_or_else(|| missing("color"))?,
+            cur_depth: fields[4].ok_or_else(|| missing("cur_depth"))?,
+            corner_colors,
+        })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| *c != '"') {
+            self.pos += 1;
+        }
+        let string = self.chars[start..self.pos].iter().collect();
+        self.expect('"')?;
+        Ok(string)
+    }
+
+    fn number(&mut self) -> Result<u32, String> {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(char::is_ascii_digit) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| format!("Expected a number at offset {}", start))
+    }
+
+    fn color(&mut self) -> Result<[u8; 4], String> {
+        let mut color = [0u8; 4];
+        self.expect('[')?;
+        for (i, channel) in color.iter_mut().enumerate() {
+            if i > 0 {
+                self.expect(',')?;
+            }
+            let start = self.pos;
+            *channel = self
+                .number()?
+                .try_into()
+                .map_err(|_| format!("Color channel out of range at offset {}", start))?;
+        }
+        self.expect(']')?;
+        Ok(color)
+    }
+
+    fn corner_colors(&mut self) -> Result<[[u8; 4]; 4], String> {
+        let mut corner_colors = [[0u8; 4]; 4];
+        self.expect('[')?;
+        for (i, corner) in corner_colors.iter_mut().enumerate() {
+            if i > 0 {
+                self.expect(',')?;
+            }
+            *corner = self.color()?;
+        }
+        self.expect(']')?;
+        Ok(corner_colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CropRegion;
+    use image::{DynamicImage, RgbaImage};
+
+    #[test]
+    fn corner_colors_survive_a_round_trip() {
+        let views = vec![
+            QuadView {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 2,
+                color: [10, 20, 30, 255],
+                cur_depth: 1,
+                corner_colors: Some([
+                    [1, 2, 3, 4],
+                    [5, 6, 7, 8],
+                    [9, 10, 11, 12],
+                    [13, 14, 15, 16],
+                ]),
+            },
+            QuadView {
+                x: 4,
+                y: 0,
+                width: 4,
+                height: 2,
+                color: [200, 100, 0, 128],
+                cur_depth: 1,
+                corner_colors: None,
+            },
+        ];
+        assert_eq!(
+            quadtree_from_json(&quadtree_to_json(&views)).unwrap(),
+            views
+        );
+    }
+
+    #[test]
+    fn malformed_json_is_rejected() {
+        assert!(quadtree_from_json("[]").unwrap().is_empty());
+        assert!(quadtree_from_json("[{\"x\": 1}]").is_err());
+        assert!(quadtree_from_json("[{\"bogus\": 1}]").is_err());
+        let short_corners = "[{\"x\": 0, \"y\": 0, \"width\": 1, \"height\": 1, \
+            \"color\": [1, 2, 3, 4], \"cur_depth\": 0, \"corner_colors\": [[1, 2, 3, 4]]}]";
+        assert!(quadtree_from_json(short_corners).is_err());
+    }
+
+    #[test]
+    fn leaves_json_is_in_crop_coordinates() {
+        let image = RgbaImage::from_fn(60, 50, |x, _| {
+            if x < 35 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let config = QuadConfig {
+            crop: Some(CropRegion {
+                x: 20,
+                y: 10,
+                width: 30,
+                height: 30,
+            }),
+            ..QuadConfig::default()
+        };
+        let quad = Quad::new(
+            DynamicImage::ImageRgba8(image),
+            20,
+            10,
+            30,
+            30,
+            config.clone(),
+            0,
+        );
+        let json = export_leaves_json(&quad.subdivide(), 30, 30, &config);
+        let expected = r##"{
+  "width": 30,
+  "height": 30,
+  "leaves": [
+    {"x": 0, "y": 0, "w": 15, "h": 15, "color": "#ff0000"},
+    {"x": 15, "y": 0, "w": 15, "h": 15, "color": "#0000ff"},
+    {"x": 0, "y": 15, "w": 15, "h": 15, "color": "#ff0000"},
+    {"x": 15, "y": 15, "w": 15, "h": 15, "color": "#0000ff"}
+  ]
+}
+"##;
+        assert_eq!(json, expected);
+    }
+}
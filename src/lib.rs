@@ -0,0 +1,18 @@
+pub mod animation;
+pub mod color;
+pub mod config;
+pub mod dither;
+pub mod error;
+pub mod exif;
+pub mod integral;
+pub mod json;
+pub mod lookup;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+pub mod process;
+pub mod quad;
+pub mod render;
+pub mod svg;
+pub mod tree;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,85 @@
+use crate::quad::Quad;
+
+/// Returns the leaf containing the point `(x, y)` in source image coordinates
+/// by checking every leaf in turn.
+pub fn find_leaf_at(quadtree_leaves: &[Quad], x: u32, y: u32) -> Option<&Quad> {
+    quadtree_leaves.iter().find(|leaf| leaf.contains(x, y))
+}
+
+/// Grid of cells over the leaves, each listing the leaves that overlap it, so
+/// repeated point lookups only check a few leaves instead of all of them.
+/// Unlike `QuadTree::leaf_at`, it also works on leaves that no longer form a
+/// tree, such as the output of `merge_similar_leaves`.
+pub struct LeafIndex<'a> {
+    quadtree_leaves: &'a [Quad],
+    origin: (u32, u32),
+    cell_size: u32,
+    columns: u32,
+    rows: u32,
+    cells: Vec<Vec<usize>>,
+}
+
+impl<'a> LeafIndex<'a> {
+    /// Builds the index, with cells sized so there are about as many cells as
+    /// leaves.
+    pub fn new(quadtree_leaves: &'a [Quad]) -> LeafIndex<'a> {
+        let bounds: Vec<(u32, u32, u32, u32)> = quadtree_leaves.iter().map(Quad::bounds).collect();
+        let min_x = bounds.iter().map(|b| b.0).min().unwrap_or(0);
+        let min_y = bounds.iter().map(|b| b.1).min().unwrap_or(0);
+        let max_x = bounds.iter().map(|b| b.0 + b.2).max().unwrap_or(0);
+        let max_y = bounds.iter().map(|b| b.1 + b.3).max().unwrap_or(0);
+        let area = (max_x - min_x) as f64 * (max_y - min_y) as f64;
+        let cell_size = ((area / quadtree_leaves.len().max(1) as f64).sqrt() as u32).max(1);
+        let columns = (max_x - min_x).div_ceil(cell_size);
+        let rows = (max_y - min_y).div_ceil(cell_size);
+
+        let mut cells: Vec<Vec<usize>> = vec![Vec::new(); (columns * rows) as usize];
+        for (index, &(x, y, width, height)) in bounds.iter().enumerate() {
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let first_column = (x - min_x) / cell_size;
+            let last_column = (x + width - 1 - min_x) / cell_size;
+            let first_row = (y - min_y) / cell_size;
+            let last_row = (y + height - 1 - min_y) / cell_size;
+            for row in first_row..=last_row {
+                for column in first_column..=last_column {
+                    cells[(row * columns + column) as usize].push(index);
+                }
+            }
+        }
+        LeafIndex {
+            quadtree_leaves,
+            origin: (min_x, min_y),
+            cell_size,
+            columns,
+            rows,
+            cells,
+        }
+    }
+
+    /// Same as `find_leaf_at`, but only checks the leaves overlapping the
+    /// point's cell.
+    pub fn find(&self, x: u32, y: u32) -> Option<&'a Quad> {
+        self.find_index(x, y)
+            .map(|index| &self.quadtree_leaves[index])
+    }
+
+    /// Same as `find`, but returns the leaf's index in the slice the index was
+    /// built from.
+    pub fn find_index(&self, x: u32, y: u32) -> Option<usize> {
+        let (origin_x, origin_y) = self.origin;
+        if x < origin_x || y < origin_y {
+            return None;
+        }
+        let column = (x - origin_x) / self.cell_size;
+        let row = (y - origin_y) / self.cell_size;
+        if column >= self.columns || row >= self.rows {
+            return None;
+        }
+        self.cells[(row * self.columns + column) as usize]
+            .iter()
+            .copied()
+            .find(|&index| self.quadtree_leaves[index].contains(x, y))
+    }
+}
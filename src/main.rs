@@ -1,19 +1,504 @@
-use image::GenericImageView;
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use quad::{generate_image, subdivide_nodes, Quad};
+use rust_quadtree_art::animation::{
+    export_animation, frames_by_depth, frames_by_reveal, frames_by_splits, RevealOrder,
+};
+use rust_quadtree_art::color::{parse_hex_color, parse_palette};
+use rust_quadtree_art::config::{FillMode, OutputOptions, QuadConfig, ShapeMode};
+use rust_quadtree_art::json::export_leaves_json;
+use rust_quadtree_art::process::process_tiled;
+#[cfg(feature = "parallel")]
+use rust_quadtree_art::quad::subdivide_nodes_parallel;
+use rust_quadtree_art::quad::{
+    histogram_csv, leaf_histogram, load_image, load_image_from_memory, merge_similar_leaves,
+    subdivide_iter, subdivide_to_count, subdivide_with_stats, Quad,
+};
+use rust_quadtree_art::render::{contact_sheet, generate_image, render_to_image, write_image};
 
-mod quad;
+/// Roughly how many frames `--animate` captures in `--target-leaves` mode, and
+/// exactly how many it captures with a `--reveal-order` other than `depth`.
+const ANIMATION_FRAME_COUNT: usize = 30;
+
+fn parse_value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("Missing or invalid value for {}!", flag))
+}
+
+/// Command-line settings that are not part of `QuadConfig`.
+#[derive(Clone)]
+struct Options {
+    output: OutputOptions,
+    max_input_dimension: Option<u32>,
+    target_leaves: Option<usize>,
+    animation_file: Option<String>,
+    fps: u32,
+    reveal_order: RevealOrder,
+    merge_threshold: Option<f64>,
+    output_format: Option<String>,
+    dry_run: bool,
+    json_file: Option<String>,
+    histogram_file: Option<String>,
+    respect_orientation: bool,
+    tile_size: Option<u32>,
+    timeout: Option<Duration>,
+    progress: bool,
+    verbose: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            output: OutputOptions::default(),
+            max_input_dimension: None,
+            target_leaves: None,
+            animation_file: None,
+            fps: 10,
+            reveal_order: RevealOrder::default(),
+            merge_threshold: None,
+            output_format: None,
+            dry_run: false,
+            json_file: None,
+            histogram_file: None,
+            respect_orientation: true,
+            tile_size: None,
+            timeout: None,
+            progress: false,
+            verbose: false,
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_path = &args[1];
-    println!("File path: {}", file_path);
-    let img = image::open(file_path).expect("File not found!");
+    let mut args = env::args().skip(1);
+    let mut input_paths = vec![args.next().expect("Missing input image path!")];
+    let mut config = QuadConfig::default();
+    let mut options = Options::default();
+    let mut corner_radius: Option<u32> = None;
+    let mut contact_sheet: Option<u32> = None;
+    let mut background_color: Option<Rgba<u8>> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-input-dimension" => {
+                options.max_input_dimension = Some(parse_value(&mut args, &arg))
+            }
+            "--output" => options.output.output_file = parse_value(&mut args, &arg),
+            "--bit-depth" => {
+                config.output_bit_depth = parse_value(&mut args, &arg);
+                if config.output_bit_depth != 8 && config.output_bit_depth != 16 {
+                    panic!("Bit depth must be 8 or 16!");
+                }
+            }
+            "--jpeg-quality" => options.output.jpeg_quality = parse_value(&mut args, &arg),
+            "--webp-quality" => options.output.webp_quality = Some(parse_value(&mut args, &arg)),
+            "--format" => options.output_format = Some(parse_value(&mut args, &arg)),
+            "--color-threshold" => config.color_threshold = parse_value(&mut args, &arg),
+            "--threshold-decay" => config.threshold_decay = parse_value(&mut args, &arg),
+            "--noise-floor" => config.noise_floor = parse_value(&mut args, &arg),
+            "--uniform" => config.uniform_grid = true,
+            "--focus" => config.channel_focus = parse_value(&mut args, &arg),
+            "--color-space" => config.color_space = parse_value(&mut args, &arg),
+            "--luma-threshold" => config.luma_threshold = parse_value(&mut args, &arg),
+            "--chroma-threshold" => config.chroma_threshold = parse_value(&mut args, &arg),
+            "--skip-transparent" => config.skip_transparent = true,
+            "--alpha-threshold" => {
+                config.alpha_threshold = parse_value(&mut args, &arg);
+                config.skip_transparent = true;
+            }
+            "--max-leaves" => config.max_leaves = Some(parse_value(&mut args, &arg)),
+            "--target-leaves" => options.target_leaves = Some(parse_value(&mut args, &arg)),
+            "--priority-area-exponent" => {
+                config.priority_area_exponent = parse_value(&mut args, &arg)
+            }
+            "--animate" => options.animation_file = Some(parse_value(&mut args, &arg)),
+            "--fps" => options.fps = parse_value(&mut args, &arg),
+            "--reveal-order" => options.reveal_order = parse_value(&mut args, &arg),
+            "--merge-threshold" => options.merge_threshold = Some(parse_value(&mut args, &arg)),
+            "--split" => config.split_strategy = parse_value(&mut args, &arg),
+            "--split-rounding" => config.split_rounding = parse_value(&mut args, &arg),
+            "--shape" => config.shape_mode = parse_value(&mut args, &arg),
+            "--fill" => config.fill_mode = parse_value(&mut args, &arg),
+            "--keep-detail" => config.fill_mode = FillMode::Source,
+            "--wireframe" => config.fill_mode = FillMode::Wireframe,
+            "--corner-radius" => corner_radius = Some(parse_value(&mut args, &arg)),
+            "--outline-color" => {
+                let hex: String = parse_value(&mut args, &arg);
+                config.outline_color =
+                    Some(parse_hex_color(&hex).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--outline-width" => config.outline_thickness = parse_value(&mut args, &arg),
+            "--no-outline" => config.outline_color = None,
+            "--depth-weighted-outline" => config.depth_weighted_outline = true,
+            "--background" => {
+                let hex: String = parse_value(&mut args, &arg);
+                background_color =
+                    Some(parse_hex_color(&hex).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--color-mode" => config.color_mode = parse_value(&mut args, &arg),
+            "--saturation-boost" => config.saturation_boost = parse_value(&mut args, &arg),
+            "--contrast" => config.contrast = parse_value(&mut args, &arg),
+            "--jitter" => config.color_jitter = parse_value(&mut args, &arg),
+            "--seed" => config.seed = parse_value(&mut args, &arg),
+            "--grayscale" => config.grayscale = true,
+            "--dither" => config.dither = true,
+            "--palette" => {
+                let palette_file: String = parse_value(&mut args, &arg);
+                let text = fs::read_to_string(&palette_file).expect("Cannot read palette file!");
+                config.palette = Some(parse_palette(&text).unwrap_or_else(|err| panic!("{}", err)));
+            }
+            "--crop" => config.crop = Some(parse_value(&mut args, &arg)),
+            "--symmetry" => config.symmetry = Some(parse_value(&mut args, &arg)),
+            "--timeout" => {
+                options.timeout = Some(Duration::from_secs_f64(parse_value(&mut args, &arg)))
+            }
+            "--dry-run" => options.dry_run = true,
+            "--progress" => options.progress = true,
+            "--verbose" => options.verbose = true,
+            "--export-json" => options.json_file = Some(parse_value(&mut args, &arg)),
+            "--histogram" => options.histogram_file = Some(parse_value(&mut args, &arg)),
+            "--ignore-orientation" => options.respect_orientation = false,
+            "--tile-size" => options.tile_size = Some(parse_value(&mut args, &arg)),
+            "--contact-sheet" => contact_sheet = Some(parse_value(&mut args, &arg)),
+            _ if !arg.starts_with("--") => input_paths.push(arg),
+            _ => panic!("Unknown argument: {}", arg),
+        }
+    }
+    if let Some(corner_radius) = corner_radius {
+        match &mut config.shape_mode {
+            ShapeMode::RoundedRect { radius } => *radius = corner_radius,
+            _ => panic!("--corner-radius requires --shape rounded!"),
+        }
+    }
+    if let Some(background_color) = background_color {
+        config.background_color = background_color;
+    } else if config.fill_mode == FillMode::Wireframe {
+        config.background_color = Rgba([255, 255, 255, 255]);
+    }
+    if config.fill_mode == FillMode::Wireframe && config.outline_color.is_none() {
+        panic!("--wireframe cannot be combined with --no-outline!");
+    }
+
+    if options.timeout.is_some() && options.target_leaves.is_some() {
+        panic!("--timeout cannot be combined with --target-leaves!");
+    }
+    if config.dither && config.palette.is_none() {
+        panic!("--dither requires --palette!");
+    }
+    if let Err(err) = config.check_palette_contrast() {
+        eprintln!("Warning: {}", err);
+    }
+
+    if let Some(columns) = contact_sheet {
+        write_contact_sheet(&input_paths, columns, &options, &config);
+    } else if let [file_path] = &input_paths[..] {
+        process_input(file_path, &options, &config);
+    } else {
+        if options.output.output_file == "-" {
+            panic!("Multiple inputs cannot be written to stdout without --contact-sheet!");
+        }
+        // Outputs are numbered in input order, e.g. output_1.png, output_2.png
+        for (index, file_path) in input_paths.iter().enumerate() {
+            let number = index + 1;
+            let options = Options {
+                output: OutputOptions {
+                    output_file: numbered_path(&options.output.output_file, number),
+                    ..options.output.clone()
+                },
+                animation_file: options
+                    .animation_file
+                    .as_deref()
+                    .map(|path| numbered_path(path, number)),
+                json_file: options
+                    .json_file
+                    .as_deref()
+                    .map(|path| numbered_path(path, number)),
+                histogram_file: options
+                    .histogram_file
+                    .as_deref()
+                    .map(|path| numbered_path(path, number)),
+                ..options.clone()
+            };
+            process_input(file_path, &options, &config);
+        }
+    }
+}
+
+/// Inserts `_number` before the extension of `path`.
+fn numbered_path(path: &str, number: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, number),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn load_input(file_path: &str, options: &Options) -> DynamicImage {
+    eprintln!("File path: {}", file_path);
+    if file_path == "-" {
+        let mut bytes: Vec<u8> = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .expect("Cannot read image from stdin!");
+        load_image_from_memory(
+            &bytes,
+            options.max_input_dimension,
+            options.respect_orientation,
+        )
+        .expect("Cannot decode image from stdin!")
+    } else {
+        load_image(
+            file_path,
+            options.max_input_dimension,
+            options.respect_orientation,
+        )
+        .expect("File not found!")
+    }
+}
+
+/// Returns the `(x, y, width, height)` of the image the art is made from.
+fn crop_bounds(img: &DynamicImage, config: &QuadConfig) -> (u32, u32, u32, u32) {
+    match config.crop {
+        Some(crop) => {
+            if crop.x + crop.width > img.width() || crop.y + crop.height > img.height() {
+                panic!("Crop region does not fit inside the image!");
+            }
+            (crop.x, crop.y, crop.width, crop.height)
+        }
+        None => (0, 0, img.width(), img.height()),
+    }
+}
 
-    let (w, h) = img.dimensions();
-    let q = Quad::new(img.clone(), 0, 0, w, h, 7, 0);
+// The leaf limit only decides whether the parallel walker can be used
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn subdivide(q: Quad, options: &Options, max_leaves: Option<usize>) -> Vec<Quad> {
+    match options.target_leaves {
+        Some(target_leaves) => subdivide_to_count(q, target_leaves),
+        #[cfg(feature = "parallel")]
+        None if max_leaves.is_none() && options.timeout.is_none() && !options.progress => {
+            subdivide_nodes_parallel(q)
+        }
+        None => {
+            let mut leaves = subdivide_iter(q);
+            if let Some(timeout) = options.timeout {
+                let cancel = Arc::new(AtomicBool::new(false));
+                let timer_cancel = Arc::clone(&cancel);
+                thread::spawn(move || {
+                    thread::sleep(timeout);
+                    timer_cancel.store(true, Ordering::Relaxed);
+                });
+                leaves = leaves.cancel_on(cancel);
+            }
+            if options.progress {
+                leaves = leaves.on_progress(|count| eprint!("\rLeaves found: {}", count));
+            }
+            let quadtree_leaves: Vec<Quad> = leaves.by_ref().collect();
+            if options.progress {
+                eprintln!();
+            }
+            if leaves.truncated() {
+                eprintln!("Warning: stopped at the leaf limit, so some quads were left unsplit");
+            }
+            if leaves.cancelled() {
+                eprintln!("Warning: timed out, so some quads were left unsplit");
+            }
+            quadtree_leaves
+        }
+    }
+}
+
+fn merge(quadtree_leaves: Vec<Quad>, options: &Options) -> Vec<Quad> {
+    match options.merge_threshold {
+        Some(merge_threshold) => merge_similar_leaves(quadtree_leaves, merge_threshold),
+        None => quadtree_leaves,
+    }
+}
+
+fn stdout_format(options: &Options) -> ImageOutputFormat {
+    match options.output_format.as_deref() {
+        None | Some("png") => ImageOutputFormat::Png,
+        Some("jpeg") | Some("jpg") => ImageOutputFormat::Jpeg(options.output.jpeg_quality),
+        Some(format) => panic!("Unsupported output format: {}", format),
+    }
+}
+
+fn write_histogram(histogram_file: &str, quadtree_leaves: &[Quad]) {
+    fs::write(
+        histogram_file,
+        histogram_csv(&leaf_histogram(quadtree_leaves)),
+    )
+    .expect("Cannot save leaf histogram!");
+}
+
+/// Runs the whole pipeline on one input and saves everything it produces.
+fn process_input(file_path: &str, options: &Options, config: &QuadConfig) {
+    let img = load_input(file_path, options);
+    let (x, y, w, h) = crop_bounds(&img, config);
+
+    if let Some(tile_size) = options.tile_size {
+        if options.dry_run
+            || options.target_leaves.is_some()
+            || options.animation_file.is_some()
+            || options.merge_threshold.is_some()
+            || options.json_file.is_some()
+            || options.histogram_file.is_some()
+            || config.symmetry.is_some()
+        {
+            panic!("--tile-size cannot be combined with --dry-run, --target-leaves, --animate, --merge-threshold, --export-json, --histogram or --symmetry!");
+        }
+        if config.output_bit_depth != 8 {
+            panic!("--tile-size only supports 8-bit output!");
+        }
+        let result = if options.output.output_file == "-" {
+            process_tiled(&img, tile_size, config, io::stdout().lock())
+        } else if options.output.output_file.to_lowercase().ends_with(".png") {
+            let file =
+                File::create(&options.output.output_file).expect("Cannot create output file!");
+            process_tiled(&img, tile_size, config, BufWriter::new(file))
+        } else {
+            panic!("--tile-size only supports PNG output!");
+        };
+        result.expect("Cannot save output image!");
+        return;
+    }
+
+    let (subdivided_width, subdivided_height) = config.subdivided_size(w, h);
+    let q = Quad::new(
+        img,
+        x,
+        y,
+        subdivided_width,
+        subdivided_height,
+        config.clone(),
+        0,
+    );
+
+    if options.dry_run {
+        if options.target_leaves.is_some() {
+            panic!("--dry-run cannot be combined with --target-leaves!");
+        }
+        let start = Instant::now();
+        let (quadtree_leaves, stats) = subdivide_with_stats(q);
+        println!("Leaf count: {}", stats.leaf_count);
+        println!("Max depth reached: {}", stats.max_depth_reached);
+        if stats.truncated {
+            println!("Stopped at the leaf limit");
+        }
+        println!("Elapsed time: {:?}", start.elapsed());
+        if let Some(histogram_file) = &options.histogram_file {
+            write_histogram(histogram_file, &quadtree_leaves);
+        }
+        return;
+    }
+
+    let animation_root = options.animation_file.as_ref().map(|_| q.clone());
+    let quadtree_leaves = subdivide(q, options, config.max_leaves);
+    if options.verbose {
+        for leaf in &quadtree_leaves {
+            eprintln!("Leaf: {}", leaf);
+        }
+    }
+
+    if let (Some(animation_file), Some(q)) = (&options.animation_file, animation_root) {
+        let frames = match (options.reveal_order, options.target_leaves) {
+            (RevealOrder::Depth, Some(target_leaves)) => {
+                let splits_per_frame = target_leaves / 3 / ANIMATION_FRAME_COUNT;
+                frames_by_splits(q, target_leaves, splits_per_frame)
+            }
+            (RevealOrder::Depth, None) => frames_by_depth(q),
+            (reveal_order, _) => {
+                frames_by_reveal(&quadtree_leaves, reveal_order, ANIMATION_FRAME_COUNT)
+            }
+        };
+        export_animation(&frames, w, h, config, animation_file, options.fps)
+            .expect("Cannot save animation!");
+    }
+
+    let quadtree_leaves = merge(quadtree_leaves, options);
+    if let Some(json_file) = &options.json_file {
+        fs::write(
+            json_file,
+            export_leaves_json(&quadtree_leaves, w, h, config),
+        )
+        .expect("Cannot save leaves JSON!");
+    }
+    if let Some(histogram_file) = &options.histogram_file {
+        write_histogram(histogram_file, &quadtree_leaves);
+    }
+    if options.output.output_file == "-" {
+        write_image(
+            &quadtree_leaves,
+            w,
+            h,
+            config,
+            &mut io::stdout().lock(),
+            stdout_format(options),
+        )
+        .expect("Cannot write output image to stdout!");
+    } else {
+        generate_image(quadtree_leaves, w, h, config, &options.output)
+            .expect("Cannot save output image!");
+    }
+}
 
-    let quadtree_leaves = subdivide_nodes(q);
-    generate_image(quadtree_leaves, w, h);
+/// Turns every input into art and saves them side by side in one image.
+fn write_contact_sheet(
+    input_paths: &[String],
+    columns: u32,
+    options: &Options,
+    config: &QuadConfig,
+) {
+    if options.dry_run
+        || options.tile_size.is_some()
+        || options.animation_file.is_some()
+        || options.json_file.is_some()
+        || options.histogram_file.is_some()
+    {
+        panic!("--contact-sheet cannot be combined with --dry-run, --tile-size, --animate, --export-json or --histogram!");
+    }
+    let images: Vec<RgbaImage> = input_paths
+        .iter()
+        .map(|file_path| {
+            let img = load_input(file_path, options);
+            let (x, y, w, h) = crop_bounds(&img, config);
+            let (subdivided_width, subdivided_height) = config.subdivided_size(w, h);
+            let q = Quad::new(
+                img,
+                x,
+                y,
+                subdivided_width,
+                subdivided_height,
+                config.clone(),
+                0,
+            );
+            let quadtree_leaves = merge(subdivide(q, options, config.max_leaves), options);
+            render_to_image(&quadtree_leaves, w, h, config)
+        })
+        .collect();
+    let sheet = DynamicImage::ImageRgba8(contact_sheet(&images, columns, config.background_color));
+    if options.output.output_file == "-" {
+        let mut bytes: Vec<u8> = Vec::new();
+        sheet
+            .write_to(&mut Cursor::new(&mut bytes), stdout_format(options))
+            .expect("Cannot encode contact sheet!");
+        io::stdout()
+            .write_all(&bytes)
+            .expect("Cannot write output image to stdout!");
+    } else {
+        sheet
+            .save(&options.output.output_file)
+            .expect("Cannot save output image!");
+    }
 }
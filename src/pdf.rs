@@ -0,0 +1,157 @@
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref};
+use std::fs;
+use std::path::Path;
+
+use crate::config::QuadConfig;
+use crate::error::QuadError;
+use crate::quad::{subdivide_nodes, subdivide_to_count_with, Quad};
+use crate::render::render_to_image;
+
+/// zlib level the page images are compressed at.
+const PDF_COMPRESSION_LEVEL: u8 = 6;
+
+/// Writes a PDF to `path` with `pages` renders of the progressive subdivision,
+/// one per page. The first page shows the root quad alone and the last the
+/// finished threshold-based subdivision, with leaf counts growing
+/// geometrically in between. Each stage comes from a single run of the
+/// `subdivide_to_count` heap. Pages are the output size in points, and
+/// transparent pixels are laid onto white, as on paper.
+pub fn export_flipbook_pdf(
+    image: DynamicImage,
+    config: &QuadConfig,
+    pages: usize,
+    path: impl AsRef<Path>,
+) -> Result<(), QuadError> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let root = Quad::new(image, 0, 0, width, height, config.clone(), 0);
+    let final_leaves = subdivide_nodes(root.clone()).len().max(1);
+    let pages = pages.max(1);
+    // Leaf count wanted on each page, from the root alone up to `final_leaves`
+    let targets: Vec<usize> = (0..pages)
+        .map(|page| match pages {
+            1 => final_leaves,
+            _ => {
+                let t = page as f64 / (pages - 1) as f64;
+                (final_leaves as f64).powf(t).round() as usize
+            }
+        })
+        .collect();
+
+    let mut stages: Vec<Vec<Quad>> = Vec::with_capacity(pages);
+    if pages > 1 {
+        stages.push(vec![root.clone()]);
+    }
+    let mut leaf_count = 1;
+    let quadtree_leaves = subdivide_to_count_with(root, final_leaves, |leaves| {
+        // Every split turns one leaf into four
+        leaf_count += 3;
+        if stages.len() + 1 < pages && leaf_count >= targets[stages.len()] {
+            let snapshot: Vec<Quad> = leaves.filter(|quad| !quad.is_skipped()).cloned().collect();
+            while stages.len() + 1 < pages && leaf_count >= targets[stages.len()] {
+                stages.push(snapshot.clone());
+            }
+        }
+    });
+    stages.push(quadtree_leaves);
+    // The heap can run dry before a target is reached; repeat the last stage
+    while stages.len() < pages {
+        stages.push(stages[stages.len() - 1].clone());
+    }
+
+    let renders: Vec<RgbaImage> = stages
+        .iter()
+        .map(|leaves| render_to_image(leaves, width, height, config))
+        .collect();
+    fs::write(path, flipbook_pdf(&renders))?;
+    Ok(())
+}
+
+/// Lays each image out on its own page, sized to the image in points.
+fn flipbook_pdf(images: &[RgbaImage]) -> Vec<u8> {
+    let mut pdf = Pdf::new();
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    // Each page takes three objects: the page, its content stream and its image
+    let ids = |page: usize| {
+        let first = 3 + 3 * page as i32;
+        (Ref::new(first), Ref::new(first + 1), Ref::new(first + 2))
+    };
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids((0..images.len()).map(|page| ids(page).0))
+        .count(images.len() as i32);
+
+    let image_name = Name(b"Im1");
+    for (index, image) in images.iter().enumerate() {
+        let (page_id, content_id, image_id) = ids(index);
+        let (width, height) = (image.width() as f32, image.height() as f32);
+
+        let mut page = pdf.page(page_id);
+        page.media_box(Rect::new(0.0, 0.0, width, height));
+        page.parent(page_tree_id);
+        page.contents(content_id);
+        page.resources().x_objects().pair(image_name, image_id);
+        page.finish();
+
+        let mut content = Content::new();
+        content.save_state();
+        content.transform([width, 0.0, 0.0, height, 0.0, 0.0]);
+        content.x_object(image_name);
+        content.restore_state();
+        pdf.stream(content_id, &content.finish());
+
+        let rgb: Vec<u8> = image
+            .pixels()
+            .flat_map(|pixel| {
+                let [r, g, b, a] = pixel.0.map(u32::from);
+                [r, g, b].map(|channel| ((channel * a + 255 * (255 - a)) / 255) as u8)
+            })
+            .collect();
+        let compressed = compress_to_vec_zlib(&rgb, PDF_COMPRESSION_LEVEL);
+        let mut xobject = pdf.image_xobject(image_id, &compressed);
+        xobject.filter(Filter::FlateDecode);
+        xobject.width(image.width() as i32);
+        xobject.height(image.height() as i32);
+        xobject.color_space().device_rgb();
+        xobject.bits_per_component(8);
+        xobject.finish();
+    }
+    pdf.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn page_count(pdf: &[u8]) -> usize {
+        let text = String::from_utf8_lossy(pdf);
+        let count = text
+            .split("/Count ")
+            .nth(1)
+            .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|digits| digits.parse().ok())
+            .expect("page tree has a /Count");
+        assert_eq!(text.matches("/Type /Page\n").count(), count);
+        count
+    }
+
+    #[test]
+    fn flipbook_pdf_has_one_page_per_stage() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, ((x ^ y) * 16) as u8, 255])
+        });
+        let config = QuadConfig::default();
+        let path = std::env::temp_dir().join("quadtree_flipbook_test.pdf");
+        export_flipbook_pdf(DynamicImage::ImageRgba8(image), &config, 5, &path).unwrap();
+        let pdf = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert_eq!(page_count(&pdf), 5);
+    }
+}
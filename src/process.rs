@@ -0,0 +1,161 @@
+use image::{DynamicImage, GenericImage, RgbaImage};
+use std::io::Write;
+
+use crate::config::QuadConfig;
+use crate::error::QuadError;
+use crate::quad::{subdivide_nodes, Quad};
+use crate::render::{render_raw, render_to_image, PixelLayout};
+
+/// Runs the whole pipeline on raw RGBA pixels and returns the rendered art as
+/// raw RGBA pixels of the same size, without touching the filesystem. Settings
+/// other than the ones passed in keep their defaults. Fails if `data` is not
+/// `width * height * 4` bytes long or the image has no pixels.
+pub fn process_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    max_depth: u32,
+    color_threshold: f64,
+    size_threshold: u32,
+) -> Result<Vec<u8>, QuadError> {
+    let expected = width as usize * height as usize * 4;
+    let size_mismatch = || QuadError::BufferSize {
+        expected,
+        actual: data.len(),
+    };
+    if data.len() != expected {
+        return Err(size_mismatch());
+    }
+    if width == 0 || height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let source = RgbaImage::from_raw(width, height, data.to_vec()).ok_or_else(size_mismatch)?;
+    let config = QuadConfig::builder()
+        .max_depth(max_depth)
+        .color_threshold(color_threshold)
+        .size_threshold(size_threshold)
+        .build();
+    let initial_quad = Quad::new(
+        DynamicImage::ImageRgba8(source),
+        0,
+        0,
+        width,
+        height,
+        config.clone(),
+        0,
+    );
+    let quadtree_leaves = subdivide_nodes(initial_quad);
+    Ok(render_raw(
+        &quadtree_leaves,
+        width,
+        height,
+        PixelLayout::Rgba,
+        &config,
+    ))
+}
+
+/// Turns `image` (or `config.crop` of it) into art one `tile_size` square tile
+/// at a time and streams the stitched result to `writer` as an 8-bit PNG.
+/// Every tile gets its own quadtree, so there are seams at tile boundaries,
+/// but only one tile's integral image and one row of tiles of output are in
+/// memory at once instead of tables and a canvas for the whole image. The
+/// decoded source itself is still held in full. `config.symmetry` is ignored.
+pub fn process_tiled<W: Write>(
+    image: &DynamicImage,
+    tile_size: u32,
+    config: &QuadConfig,
+    writer: W,
+) -> Result<(), QuadError> {
+    let (origin_x, origin_y, width, height) = match config.crop {
+        Some(crop) => (crop.x, crop.y, crop.width, crop.height),
+        None => (0, 0, image.width(), image.height()),
+    };
+    if width == 0 || height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let tile_size = tile_size.max(1);
+    let tile_config = QuadConfig {
+        crop: None,
+        symmetry: None,
+        ..config.clone()
+    };
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header()?;
+    let mut stream = png_writer.stream_writer()?;
+    for band_y in (0..height).step_by(tile_size as usize) {
+        let band_height = tile_size.min(height - band_y);
+        let mut band = RgbaImage::new(width, band_height);
+        for tile_x in (0..width).step_by(tile_size as usize) {
+            let tile_width = tile_size.min(width - tile_x);
+            let tile = image.crop_imm(
+                origin_x + tile_x,
+                origin_y + band_y,
+                tile_width,
+                band_height,
+            );
+            let initial_quad =
+                Quad::new(tile, 0, 0, tile_width, band_height, tile_config.clone(), 0);
+            let quadtree_leaves = subdivide_nodes(initial_quad);
+            let tile_image =
+                render_to_image(&quadtree_leaves, tile_width, band_height, &tile_config);
+            band.copy_from(&tile_image, tile_x, 0)?;
+        }
+        stream.write_all(band.as_raw())?;
+    }
+    stream.finish()?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_image_returns_raw_pixels_of_the_same_size() {
+        let source = RgbaImage::from_fn(24, 16, |x, y| {
+            image::Rgba([(x * 10) as u8, (y * 15) as u8, 90, 255])
+        });
+        let output = process_image(source.as_raw(), 24, 16, 7, 10.0, 4).unwrap();
+        assert_eq!(output.len(), 24 * 16 * 4);
+
+        let config = QuadConfig::builder()
+            .max_depth(7)
+            .color_threshold(10.0)
+            .size_threshold(4)
+            .build();
+        let quad = Quad::new(
+            DynamicImage::ImageRgba8(source),
+            0,
+            0,
+            24,
+            16,
+            config.clone(),
+            0,
+        );
+        let expected = render_to_image(&subdivide_nodes(quad), 24, 16, &config);
+        assert_eq!(output, expected.into_raw());
+    }
+
+    #[test]
+    fn process_image_rejects_bad_buffers() {
+        assert!(matches!(
+            process_image(&[0; 10], 2, 2, 7, 10.0, 4),
+            Err(QuadError::BufferSize {
+                expected: 16,
+                actual: 10
+            })
+        ));
+        assert!(matches!(
+            process_image(&[], 0, 0, 7, 10.0, 4),
+            Err(QuadError::EmptyImage)
+        ));
+        assert!(matches!(
+            process_image(&[], 0, 5, 7, 10.0, 4),
+            Err(QuadError::EmptyImage)
+        ));
+    }
+}
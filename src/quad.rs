@@ -1,19 +1,115 @@
-use image::{GenericImageView, Pixel, Rgba, RgbaImage};
-use imageproc::drawing::draw_line_segment_mut;
-use std::collections::VecDeque;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgba};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
 use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use std::vec::Vec;
 
+use crate::color::{
+    adjust_color, color_distance, delta_e76, depth_heatmap_color, focus_distance, jitter_color,
+    nearest_palette_color, posterize, rgb_to_ycbcr, srgb_to_lab, to_grayscale,
+};
+use crate::config::{
+    ChannelFocus, ColorAggregation, ColorMetric, ColorMode, ColorSpace, FillMode, QuadConfig,
+    SplitRounding, SplitStrategy, ThresholdMetric,
+};
+use crate::error::QuadError;
+use crate::exif::{apply_orientation, read_orientation};
+use crate::integral::{is_sixteen_bit, EdgeMaskedIntegral, GradientIntegral, IntegralImage};
+
 #[derive(Clone)]
 pub struct Quad {
-    image: image::DynamicImage,
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-    color: Rgba<u8>,
-    max_depth: u32,
-    cur_depth: u32,
+    pub(crate) image: Arc<DynamicImage>,
+    /// Built on first use, so runs that never read a mean from it, such as
+    /// ones using `ColorAggregation::Median`, skip it.
+    pub(crate) integral: Arc<OnceLock<IntegralImage>>,
+    /// Only built when `config.threshold_metric` is `EdgeDensity`.
+    pub(crate) gradients: Option<Arc<GradientIntegral>>,
+    /// Only built when `config.threshold_metric` is `Variance` or
+    /// `EdgeMaskedVariance`.
+    pub(crate) squares: Option<Arc<IntegralImage>>,
+    /// Only built when `config.threshold_metric` is `EdgeMaskedVariance`.
+    pub(crate) edge_mask: Option<Arc<EdgeMaskedIntegral>>,
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) color: Rgba<u8>,
+    pub(crate) cur_depth: u32,
+    pub(crate) config: Arc<QuadConfig>,
+}
+
+/// Geometry and color of a quad without its source image, enough to render it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct QuadView {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Average RGBA color of the quad.
+    pub color: [u8; 4],
+    pub cur_depth: u32,
+    /// Source colors at the top-left, top-right, bottom-left and bottom-right
+    /// pixels, sampled when `config.fill_mode` is `CornerGradient`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub corner_colors: Option<[[u8; 4]; 4]>,
+}
+
+impl QuadView {
+    /// Returns the color the quad is rendered with, after any post-processing in `config`.
+    pub fn fill_color(&self, config: &QuadConfig) -> Rgba<u8> {
+        if config.color_mode == ColorMode::Depth {
+            return depth_heatmap_color(self.cur_depth, config.max_depth);
+        }
+        self.post_process(Rgba(self.color), config)
+    }
+
+    /// Returns the corner colors a gradient fill blends between, after the same
+    /// post-processing as `fill_color`, or `None` if the quad is filled flat.
+    pub fn corner_fill_colors(&self, config: &QuadConfig) -> Option<[Rgba<u8>; 4]> {
+        if config.color_mode == ColorMode::Depth {
+            return None;
+        }
+        self.corner_colors
+            .map(|corners| corners.map(|corner| self.post_process(Rgba(corner), config)))
+    }
+
+    fn post_process(&self, color: Rgba<u8>, config: &QuadConfig) -> Rgba<u8> {
+        let color = self.adjust_fill(color, config);
+        match &config.palette {
+            Some(palette) => nearest_palette_color(color, palette, config.color_metric),
+            None => color,
+        }
+    }
+
+    /// Applies the post-processing in `config` that comes before palette snapping.
+    pub(crate) fn adjust_fill(&self, mut color: Rgba<u8>, config: &QuadConfig) -> Rgba<u8> {
+        if config.saturation_boost != 1.0 || config.contrast != 1.0 {
+            color = adjust_color(color, config.saturation_boost, config.contrast);
+        }
+        if config.color_jitter > 0 {
+            color = jitter_color(color, config.color_jitter, config.seed, self.x, self.y);
+        }
+        if let Some(levels) = config.posterize_levels {
+            color = posterize(color, levels);
+        }
+        if config.grayscale {
+            color = to_grayscale(color);
+        }
+        color
+    }
 }
 
 impl fmt::Display for Quad {
@@ -21,197 +117,1680 @@ impl fmt::Display for Quad {
         write!(
             f,
             "(<{}, {}> {} x {}, cur depth: {}, max depth: {})",
-            self.x, self.y, self.width, self.height, self.cur_depth, self.max_depth
+            self.x, self.y, self.width, self.height, self.cur_depth, self.config.max_depth
         )
     }
 }
 
+/// Leaves out the source image and its integral tables, which are shared by
+/// every quad of a tree and too large to print.
+impl fmt::Debug for Quad {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Quad")
+            .field("x", &self.x)
+            .field("y", &self.y)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("color", &self.color.0)
+            .field("cur_depth", &self.cur_depth)
+            .field("max_depth", &self.config.max_depth)
+            .field("color_threshold", &self.config.color_threshold)
+            .field("size_threshold", &self.config.size_threshold)
+            .field("color_metric", &self.config.color_metric)
+            .field("threshold_metric", &self.config.threshold_metric)
+            .field("color_space", &self.config.color_space)
+            .field("split_strategy", &self.config.split_strategy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Opens an image and, if its longest side exceeds `max_input_dimension`,
+/// downscales it (preserving the aspect ratio) so subdivision time stays bounded.
+/// With `respect_orientation`, photos are first turned upright according to
+/// their EXIF orientation.
+pub fn load_image(
+    file_path: &str,
+    max_input_dimension: Option<u32>,
+    respect_orientation: bool,
+) -> Result<DynamicImage, QuadError> {
+    let bytes = fs::read(file_path)?;
+    let img = image::load_from_memory_with_format(&bytes, ImageFormat::from_path(file_path)?)?;
+    prepare_image(img, &bytes, max_input_dimension, respect_orientation)
+}
+
+/// Same as `load_image`, but decodes an in-memory encoded image, such as one
+/// read from stdin. The format is guessed from the data.
+pub fn load_image_from_memory(
+    bytes: &[u8],
+    max_input_dimension: Option<u32>,
+    respect_orientation: bool,
+) -> Result<DynamicImage, QuadError> {
+    let img = image::load_from_memory(bytes)?;
+    prepare_image(img, bytes, max_input_dimension, respect_orientation)
+}
+
+fn prepare_image(
+    img: DynamicImage,
+    bytes: &[u8],
+    max_input_dimension: Option<u32>,
+    respect_orientation: bool,
+) -> Result<DynamicImage, QuadError> {
+    let img = match read_orientation(bytes) {
+        Some(orientation) if respect_orientation => apply_orientation(img, orientation),
+        _ => img,
+    };
+    if img.width() == 0 || img.height() == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    match max_input_dimension {
+        Some(max_dim) if img.width().max(img.height()) > max_dim => {
+            Ok(img.resize(max_dim, max_dim, FilterType::Lanczos3))
+        }
+        _ => Ok(img),
+    }
+}
+
 pub fn subdivide_nodes(initial_quad: Quad) -> Vec<Quad> {
-    let mut deque: VecDeque<Quad> = VecDeque::new();
-    deque.push_back(initial_quad.clone());
-    let mut quadtree_leaves: Vec<Quad> = Vec::new();
-    let color_distance_threshold = 10.0;
-    let image_dimension_threshold = 5;
+    subdivide_with_stats(initial_quad).0
+}
 
-    while !deque.is_empty() {
-        let mut next_quad = deque.pop_front().expect("Cannot dequeue from empty queue.");
+/// How many leaves a `Leaves` iterator yields between calls to the callback
+/// passed to `Leaves::on_progress`.
+pub const PROGRESS_INTERVAL: usize = 1000;
 
-        println!("Next quad: {}", next_quad);
-        if next_quad.x >= initial_quad.width {
-            continue;
+/// Lazily yields the leaves of `subdivide_nodes`, in the same order, as soon as
+/// each one is found, so they can be consumed without collecting them first.
+pub fn subdivide_iter(initial_quad: Quad) -> Leaves {
+    Leaves {
+        max_leaves: initial_quad.config.max_leaves,
+        deque: VecDeque::from([initial_quad]),
+        yielded: 0,
+        truncated: false,
+        cancel: None,
+        cancelled: false,
+        progress: None,
+    }
+}
+
+/// Same as `subdivide_nodes`, but stops splitting as soon as `cancel` is set,
+/// for example by a timer thread enforcing a timeout. The quads that were still
+/// waiting to be checked are returned as leaves, so the result still covers the
+/// whole image, just more coarsely. Also returns whether it was cancelled.
+pub fn subdivide_cancellable(initial_quad: Quad, cancel: Arc<AtomicBool>) -> (Vec<Quad>, bool) {
+    let mut leaves = subdivide_iter(initial_quad).cancel_on(cancel);
+    let quadtree_leaves: Vec<Quad> = leaves.by_ref().collect();
+    (quadtree_leaves, leaves.cancelled())
+}
+
+/// Iterator returned by `subdivide_iter`. Only the quads still waiting to be
+/// checked are kept in memory.
+pub struct Leaves {
+    deque: VecDeque<Quad>,
+    max_leaves: Option<usize>,
+    yielded: usize,
+    truncated: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    cancelled: bool,
+    progress: Option<Box<dyn FnMut(usize) + Send>>,
+}
+
+impl Leaves {
+    /// Whether a quad was left unsplit so far because splitting it would have
+    /// exceeded `config.max_leaves`.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Stops splitting once `cancel` is set. After that, quads that would have
+    /// been split are yielded as they are instead.
+    pub fn cancel_on(mut self, cancel: Arc<AtomicBool>) -> Leaves {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Calls `progress` with the number of leaves yielded so far after every
+    /// `PROGRESS_INTERVAL` leaves, and once more with the final count when the
+    /// iterator runs out.
+    pub fn on_progress(mut self, progress: impl FnMut(usize) + Send + 'static) -> Leaves {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Whether the flag passed to `cancel_on` left some quads unsplit so far.
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Whether splitting `quad`, which was just dequeued, would take the
+    /// eventual leaf count past `max_leaves`.
+    fn exceeds_max_leaves(&self, quad: &Quad) -> bool {
+        let Some(max_leaves) = self.max_leaves else {
+            return false;
+        };
+        let child_count = if quad.splits_binary() { 2 } else { 4 };
+        self.yielded + self.deque.len() + child_count > max_leaves
+    }
+
+    /// Finds the next leaf, splitting the quads in the way.
+    fn next_leaf(&mut self) -> Option<Quad> {
+        while let Some(next_quad) = self.deque.pop_front() {
+            if !next_quad.should_subdivide() {
+                if next_quad.is_skipped() {
+                    continue;
+                }
+                self.yielded += 1;
+                return Some(next_quad);
+            }
+            if self.exceeds_max_leaves(&next_quad) {
+                self.truncated = true;
+                self.yielded += 1;
+                return Some(next_quad);
+            }
+            let is_cancelled = self
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(AtomicOrdering::Relaxed));
+            if is_cancelled {
+                self.cancelled = true;
+                self.yielded += 1;
+                return Some(next_quad);
+            }
+            self.deque.extend(next_quad.split());
         }
+        None
+    }
+}
+
+impl Iterator for Leaves {
+    type Item = Quad;
 
-        if next_quad.cur_depth < next_quad.max_depth
-            && next_quad.calc_avg_color_distance() > color_distance_threshold
-            && next_quad.width > image_dimension_threshold
-            && next_quad.height > image_dimension_threshold
-        {
-            let children = next_quad.subdivide();
-            for i in 0..4 {
-                let child_node = children[i].clone();
-                deque.push_back(child_node);
+    fn next(&mut self) -> Option<Quad> {
+        let leaf = self.next_leaf();
+        if let Some(progress) = self.progress.as_mut() {
+            if leaf.is_none() || self.yielded.is_multiple_of(PROGRESS_INTERVAL) {
+                progress(self.yielded);
+            }
+            if leaf.is_none() {
+                // Report the final count only once
+                self.progress = None;
             }
+        }
+        leaf
+    }
+}
+
+/// Summary of a subdivision result, for tuning `max_depth` and `color_threshold`.
+/// Leaf sizes are areas in pixels, and are 0 when there are no leaves, as when
+/// `config.skip_transparent` drops them all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuadStats {
+    pub leaf_count: usize,
+    pub max_depth_reached: u32,
+    pub min_leaf_size: u32,
+    pub avg_leaf_size: f64,
+    /// Whether `config.max_leaves` stopped some quads from being split.
+    pub truncated: bool,
+}
+
+/// Same as `subdivide_nodes`, but also returns statistics gathered as the
+/// leaves are found.
+pub fn subdivide_with_stats(initial_quad: Quad) -> (Vec<Quad>, QuadStats) {
+    let mut quadtree_leaves: Vec<Quad> = Vec::new();
+    let mut max_depth_reached = 0;
+    let mut min_leaf_size = u32::MAX;
+    let mut total_leaf_size: u64 = 0;
+
+    let mut leaves = subdivide_iter(initial_quad);
+    for leaf in leaves.by_ref() {
+        let leaf_size = leaf.width * leaf.height;
+        max_depth_reached = max_depth_reached.max(leaf.cur_depth);
+        min_leaf_size = min_leaf_size.min(leaf_size);
+        total_leaf_size += leaf_size as u64;
+        quadtree_leaves.push(leaf);
+    }
+
+    let (min_leaf_size, avg_leaf_size) = match quadtree_leaves.len() {
+        0 => (0, 0.0),
+        leaf_count => (min_leaf_size, total_leaf_size as f64 / leaf_count as f64),
+    };
+    let stats = QuadStats {
+        leaf_count: quadtree_leaves.len(),
+        max_depth_reached,
+        min_leaf_size,
+        avg_leaf_size,
+        truncated: leaves.truncated(),
+    };
+    (quadtree_leaves, stats)
+}
+
+/// Counts the leaves at each depth, shallowest first.
+pub fn leaf_histogram(quadtree_leaves: &[Quad]) -> BTreeMap<u32, usize> {
+    let mut histogram = BTreeMap::new();
+    for quad in quadtree_leaves {
+        *histogram.entry(quad.cur_depth).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// Formats a `leaf_histogram` as CSV with a `depth,leaf_count` header row.
+pub fn histogram_csv(histogram: &BTreeMap<u32, usize>) -> String {
+    let mut csv = String::from("depth,leaf_count\n");
+    for (depth, leaf_count) in histogram {
+        csv.push_str(&format!("{},{}\n", depth, leaf_count));
+    }
+    csv
+}
+
+/// Quad in the `subdivide_to_count` heap, ordered by its color error, with ties
+/// going to the quad nearest the top, then the left. Live quads never overlap,
+/// so no two share a corner and the order is total.
+struct PrioritizedQuad {
+    priority: f64,
+    quad: Quad,
+}
+
+impl PrioritizedQuad {
+    fn new(quad: Quad) -> PrioritizedQuad {
+        let area = quad.width as f64 * quad.height as f64;
+        PrioritizedQuad {
+            priority: quad.calc_avg_color_distance()
+                * area.powf(quad.config.priority_area_exponent),
+            quad,
+        }
+    }
+}
+
+impl PartialEq for PrioritizedQuad {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PrioritizedQuad {}
+
+impl PartialOrd for PrioritizedQuad {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrioritizedQuad {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The heap pops the greatest entry first, so smaller coordinates compare greater
+        self.priority
+            .total_cmp(&other.priority)
+            .then_with(|| other.quad.y.cmp(&self.quad.y))
+            .then_with(|| other.quad.x.cmp(&self.quad.x))
+    }
+}
+
+/// Repeatedly splits the leaf with the highest color error (distance times area
+/// to the power `config.priority_area_exponent`) until there are at least
+/// `target_leaves` leaves, so detail concentrates on the busiest regions. Each
+/// split adds three leaves, so the count can overshoot by up to two. The color threshold is ignored; quads that hit the depth or size
+/// limits are kept as they are. Leaves dropped by `config.skip_transparent` are
+/// removed at the end, so fewer than `target_leaves` may be returned. Quads with
+/// equal error are split in reading order (top to bottom, then left to right),
+/// so the output is the same on every run.
+pub fn subdivide_to_count(initial_quad: Quad, target_leaves: usize) -> Vec<Quad> {
+    subdivide_to_count_with(initial_quad, target_leaves, |_| {})
+}
+
+/// Drives `subdivide_to_count`, calling `on_split` with the current leaves
+/// (in no particular order) after every split.
+pub(crate) fn subdivide_to_count_with<F>(
+    initial_quad: Quad,
+    target_leaves: usize,
+    mut on_split: F,
+) -> Vec<Quad>
+where
+    F: FnMut(&mut dyn Iterator<Item = &Quad>),
+{
+    let mut heap: BinaryHeap<PrioritizedQuad> = BinaryHeap::new();
+    heap.push(PrioritizedQuad::new(initial_quad));
+    let mut quadtree_leaves: Vec<Quad> = Vec::new();
+
+    while heap.len() + quadtree_leaves.len() < target_leaves {
+        let Some(PrioritizedQuad { quad, .. }) = heap.pop() else {
+            break;
+        };
+        if quad.can_subdivide() {
+            heap.extend(quad.split().into_iter().map(PrioritizedQuad::new));
+            on_split(
+                &mut heap
+                    .iter()
+                    .map(|entry| &entry.quad)
+                    .chain(quadtree_leaves.iter()),
+            );
         } else {
-            quadtree_leaves.push(next_quad);
+            quadtree_leaves.push(quad);
         }
     }
-    return quadtree_leaves;
+    quadtree_leaves.extend(heap.into_iter().map(|entry| entry.quad));
+    quadtree_leaves.retain(|quad| !quad.is_skipped());
+    quadtree_leaves
 }
 
-pub fn generate_image(quadtree_leaves: Vec<Quad>, image_width: u32, image_height: u32) {
-    let mut output_image = RgbaImage::new(image_width, image_height);
-    let black = Rgba([0, 0, 0, 255]);
+/// Same as `subdivide_nodes`, but subdivides the children of each quad on
+/// separate rayon tasks. Leaves come back in depth-first rather than
+/// breadth-first order. With `config.max_leaves` set it falls back to
+/// `subdivide_nodes`, since the limit depends on the breadth-first order.
+#[cfg(feature = "parallel")]
+pub fn subdivide_nodes_parallel(initial_quad: Quad) -> Vec<Quad> {
+    if initial_quad.config.max_leaves.is_some() {
+        return subdivide_nodes(initial_quad);
+    }
+    collect_leaves_parallel(initial_quad)
+}
 
-    for leaf in quadtree_leaves {
-        for x in (leaf.x)..(leaf.x + leaf.width) {
-            for y in (leaf.y)..(leaf.y + leaf.height) {
-                if x >= output_image.width() || y >= output_image.height() {
-                    continue;
-                }
-                output_image.put_pixel(x, y, leaf.color);
-            }
+#[cfg(feature = "parallel")]
+fn collect_leaves_parallel(quad: Quad) -> Vec<Quad> {
+    if !quad.should_subdivide() {
+        return if quad.is_skipped() {
+            Vec::new()
+        } else {
+            vec![quad]
+        };
+    }
+
+    use rayon::prelude::*;
+    quad.split()
+        .into_par_iter()
+        .flat_map_iter(collect_leaves_parallel)
+        .collect()
+}
+
+/// How many times longer than wide a quad must be for `SplitStrategy::Adaptive`
+/// to halve it instead of splitting it into quadrants.
+const ADAPTIVE_SPLIT_ASPECT_RATIO: f64 = 2.0;
+
+/// Repeatedly merges pairs of neighboring leaves that share a full edge and
+/// whose average colors are within `merge_threshold` CIE76 Delta E, so nearly
+/// identical cells that were only split by a narrow margin become one leaf.
+pub fn merge_similar_leaves(quadtree_leaves: Vec<Quad>, merge_threshold: f64) -> Vec<Quad> {
+    let mut quadtree_leaves = quadtree_leaves;
+    loop {
+        let leaf_count = quadtree_leaves.len();
+        quadtree_leaves = merge_pass(quadtree_leaves, merge_threshold, true);
+        quadtree_leaves = merge_pass(quadtree_leaves, merge_threshold, false);
+        if quadtree_leaves.len() == leaf_count {
+            return quadtree_leaves;
         }
-        // Draw outline around leaf
-        draw_line_segment_mut(
-            &mut output_image,
-            (leaf.x as f32, leaf.y as f32),
-            ((leaf.x + leaf.width) as f32, leaf.y as f32),
-            black,
-        );
-        draw_line_segment_mut(
-            &mut output_image,
-            (leaf.x as f32, (leaf.y + leaf.height) as f32),
-            ((leaf.x + leaf.width) as f32, (leaf.y + leaf.height) as f32),
-            black,
-        );
-        draw_line_segment_mut(
-            &mut output_image,
-            (leaf.x as f32, leaf.y as f32),
-            (leaf.x as f32, (leaf.y + leaf.height) as f32),
-            black,
-        );
-        draw_line_segment_mut(
-            &mut output_image,
-            ((leaf.x + leaf.width) as f32, leaf.y as f32),
-            ((leaf.x + leaf.width) as f32, (leaf.y + leaf.height) as f32),
-            black,
-        );
     }
-    output_image.save("output.png").unwrap();
+}
+
+/// Merges each leaf with the similar leaf directly to its right (or below it
+/// when `horizontal` is false), if that leaf spans exactly the same rows (or
+/// columns).
+fn merge_pass(quadtree_leaves: Vec<Quad>, merge_threshold: f64, horizontal: bool) -> Vec<Quad> {
+    // Leaves keyed by where their left (or top) edge starts, plus its length
+    let edge_key = |quad: &Quad| {
+        if horizontal {
+            (quad.x, quad.y, quad.height)
+        } else {
+            (quad.x, quad.y, quad.width)
+        }
+    };
+    let leaf_by_edge: HashMap<(u32, u32, u32), usize> = quadtree_leaves
+        .iter()
+        .enumerate()
+        .map(|(index, quad)| (edge_key(quad), index))
+        .collect();
+    let mut slots: Vec<Option<Quad>> = quadtree_leaves.into_iter().map(Some).collect();
+
+    for index in 0..slots.len() {
+        let Some(quad) = &slots[index] else {
+            continue;
+        };
+        let neighbor_key = if horizontal {
+            (quad.x + quad.width, quad.y, quad.height)
+        } else {
+            (quad.x, quad.y + quad.height, quad.width)
+        };
+        let Some(&neighbor_index) = leaf_by_edge.get(&neighbor_key) else {
+            continue;
+        };
+        let Some(neighbor) = &slots[neighbor_index] else {
+            continue;
+        };
+        if delta_e76(srgb_to_lab(quad.color), srgb_to_lab(neighbor.color)) > merge_threshold {
+            continue;
+        }
+
+        let (width, height) = if horizontal {
+            (quad.width + neighbor.width, quad.height)
+        } else {
+            (quad.width, quad.height + neighbor.height)
+        };
+        let mut merged = quad.child(quad.x, quad.y, width, height);
+        merged.cur_depth = quad.cur_depth.min(neighbor.cur_depth);
+        slots[index] = Some(merged);
+        slots[neighbor_index] = None;
+    }
+    slots.into_iter().flatten().collect()
+}
+
+/// Longest side of the thumbnail sampled by `dominant_color`.
+const DOMINANT_COLOR_SAMPLE_SIZE: u32 = 64;
+
+/// Returns the most representative color of an image, found from a coarse
+/// histogram over a downsampled copy. The result is the average of the pixels
+/// in the most populated histogram bin, so it is not snapped to the bin grid.
+pub fn dominant_color(image: &DynamicImage) -> Rgba<u8> {
+    let sample = image
+        .thumbnail(DOMINANT_COLOR_SAMPLE_SIZE, DOMINANT_COLOR_SAMPLE_SIZE)
+        .to_rgba8();
+
+    // 4 bits per channel gives 4096 bins
+    let mut counts = vec![0u32; 4096];
+    let mut sums = vec![[0u32; 3]; 4096];
+    for pixel in sample.pixels() {
+        let [r, g, b, _] = pixel.0;
+        let bin = ((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4);
+        counts[bin] += 1;
+        sums[bin][0] += r as u32;
+        sums[bin][1] += g as u32;
+        sums[bin][2] += b as u32;
+    }
+
+    let (bin, count) = counts
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, count)| **count)
+        .expect("Histogram has no bins.");
+    if *count == 0 {
+        return Rgba([0, 0, 0, 255]);
+    }
+    let [r, g, b] = sums[bin].map(|sum| (sum / count) as u8);
+    Rgba([r, g, b, 255])
+}
+
+/// Rough time it takes to scan one source pixel, used to turn scan counts into a duration.
+const NANOS_PER_PIXEL_SCAN: u64 = 4;
+
+/// Rough time it takes to read one quad's average color from the integral image.
+const NANOS_PER_AVERAGE_LOOKUP: u64 = 40;
+
+/// Ballpark figures for a run, returned by `estimate_cost`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProcessingEstimate {
+    pub pixel_scans: u64,
+    pub estimated_leaves: u64,
+    pub estimated_duration: Duration,
+}
+
+/// Estimates the work a run will take from the image size and config alone,
+/// without touching any pixels.
+pub fn estimate_cost(width: u32, height: u32, config: &QuadConfig) -> ProcessingEstimate {
+    // Depth stops growing once quads shrink to the size threshold
+    let mut levels = 0;
+    let mut side = width.min(height);
+    while levels < config.max_depth && side > config.size_threshold {
+        side = side.div_ceil(2);
+        levels += 1;
+    }
+
+    // Every level scans each pixel once for the color distance, unless the
+    // threshold metric reads it from a table built in a single pass. Averages
+    // come from the integral image, so they cost one constant-time lookup per quad.
+    let table_metric = config.threshold_metric != ThresholdMetric::MeanDistance
+        && config.channel_focus == ChannelFocus::All
+        && config.color_space == ColorSpace::Rgb;
+    let scanned_levels = if table_metric { 0 } else { levels as u64 };
+    let area = width as u64 * height as u64;
+    let pixel_scans = area * (scanned_levels + 1);
+    let average_lookups: u64 = (0..=levels)
+        .map(|level| 4u64.saturating_pow(level).min(area))
+        .sum();
+
+    // Real images usually refine only part of the deepest level
+    let min_leaf_area = (config.size_threshold.max(1) as u64).pow(2);
+    let full_leaves = 4u64.saturating_pow(levels).min(area / min_leaf_area);
+    let estimated_leaves = (full_leaves / 4).max(1);
+
+    ProcessingEstimate {
+        pixel_scans,
+        estimated_leaves,
+        estimated_duration: Duration::from_nanos(
+            pixel_scans * NANOS_PER_PIXEL_SCAN + average_lookups * NANOS_PER_AVERAGE_LOOKUP,
+        ),
+    }
 }
 
 impl Quad {
     pub fn new(
-        image: image::DynamicImage,
+        image: DynamicImage,
         x: u32,
         y: u32,
         width: u32,
         height: u32,
-        max_depth: u32,
+        config: QuadConfig,
         cur_depth: u32,
     ) -> Quad {
-        Quad {
-            image: image,
-            x: x,
-            y: y,
-            width: width,
-            height: height,
+        Quad::from_shared(Arc::new(image), x, y, width, height, config, cur_depth)
+    }
+
+    /// Same as `new`, but for an image that is already shared, so the same
+    /// decoded image can be run through several configs without cloning it.
+    pub fn from_shared(
+        image: Arc<DynamicImage>,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        config: QuadConfig,
+        cur_depth: u32,
+    ) -> Quad {
+        let gradients = (config.threshold_metric == ThresholdMetric::EdgeDensity)
+            .then(|| Arc::new(GradientIntegral::new(&image)));
+        let squares = matches!(
+            config.threshold_metric,
+            ThresholdMetric::Variance | ThresholdMetric::EdgeMaskedVariance
+        )
+        .then(|| Arc::new(IntegralImage::of_squares(&image)));
+        let edge_mask = (config.threshold_metric == ThresholdMetric::EdgeMaskedVariance)
+            .then(|| Arc::new(EdgeMaskedIntegral::new(&image, config.edge_mask_threshold)));
+        let mut quad = Quad {
+            image,
+            integral: Arc::new(OnceLock::new()),
+            gradients,
+            squares,
+            edge_mask,
+            x,
+            y,
+            width,
+            height,
+            color: Rgba([0, 0, 0, 255]),
+            cur_depth,
+            config: Arc::new(config),
+        };
+        quad.color = quad.calc_color();
+        quad
+    }
+
+    /// Builds a quad one level deeper that shares this quad's image, integral
+    /// images and config, so nothing is copied per child.
+    fn child(&self, x: u32, y: u32, width: u32, height: u32) -> Quad {
+        let mut quad = Quad {
+            image: Arc::clone(&self.image),
+            integral: Arc::clone(&self.integral),
+            gradients: self.gradients.clone(),
+            squares: self.squares.clone(),
+            edge_mask: self.edge_mask.clone(),
+            x,
+            y,
+            width,
+            height,
             color: Rgba([0, 0, 0, 255]),
-            cur_depth: cur_depth,
-            max_depth: max_depth,
+            cur_depth: self.cur_depth + 1,
+            config: Arc::clone(&self.config),
+        };
+        quad.color = quad.calc_color();
+        quad
+    }
+
+    /// Returns the color a leaf is rendered with, after any post-processing in `config`.
+    pub fn fill_color(&self, config: &QuadConfig) -> Rgba<u8> {
+        self.view().fill_color(config)
+    }
+
+    /// Left edge of the quad in source image coordinates.
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    /// Top edge of the quad in source image coordinates.
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// How many splits deep the quad is, with the root quad at 0.
+    pub fn depth(&self) -> u32 {
+        self.cur_depth
+    }
+
+    /// Returns the quad's `(x, y, width, height)` in source image coordinates.
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    /// Returns the quad's color before any post-processing from the config.
+    pub fn color(&self) -> Rgba<u8> {
+        self.color
+    }
+
+    /// Whether the quad is left out of the leaves because `config.skip_transparent`
+    /// is set and its average alpha is below `config.alpha_threshold`.
+    pub fn is_skipped(&self) -> bool {
+        self.config.skip_transparent && self.color.0[3] < self.config.alpha_threshold
+    }
+
+    /// Whether the point `(x, y)` lies inside the quad.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// Returns the quad's geometry and color, detached from the source image.
+    pub fn view(&self) -> QuadView {
+        QuadView {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            color: self.color.0,
+            cur_depth: self.cur_depth,
+            corner_colors: (self.config.fill_mode == FillMode::CornerGradient)
+                .then(|| self.sample_corners()),
         }
     }
 
-    pub fn calc_avg_color_distance(&self) -> f64 {
-        let mut color_sum: f64 = 0.0;
-        for x in (self.x)..(self.x + self.width) {
-            for y in (self.y)..(self.y + self.height) {
-                if x >= self.image.width() || y >= self.image.height() {
-                    continue;
-                }
-                let pixel = self.image.get_pixel(x, y);
-                let pixel_rgba = pixel.to_rgba();
-                let rgba_arr = pixel_rgba.0;
+    /// Returns the source pixels at the quad's top-left, top-right, bottom-left
+    /// and bottom-right corners, clipped to the image.
+    fn sample_corners(&self) -> [[u8; 4]; 4] {
+        let right = (self.x + self.width)
+            .min(self.image.width())
+            .saturating_sub(1);
+        let bottom = (self.y + self.height)
+            .min(self.image.height())
+            .saturating_sub(1);
+        let (left, top) = (self.x.min(right), self.y.min(bottom));
+        [(left, top), (right, top), (left, bottom), (right, bottom)]
+            .map(|(x, y)| self.image.get_pixel(x, y).0)
+    }
 
-                let avg_color_rgba = self.color.to_rgba().0;
-                color_sum += (avg_color_rgba[0] as f64 - rgba_arr[0] as f64).abs(); // red
-                color_sum += (avg_color_rgba[1] as f64 - rgba_arr[1] as f64).abs(); // green
-                color_sum += (avg_color_rgba[2] as f64 - rgba_arr[2] as f64).abs(); // blue
+    pub fn should_subdivide(&self) -> bool {
+        if !self.can_subdivide() {
+            return false;
+        }
+        if self.config.uniform_grid {
+            return true;
+        }
+        let decay = self.config.threshold_decay.powi(self.cur_depth as i32);
+        let noise_floor = self.config.noise_floor;
+        match self.config.color_space {
+            ColorSpace::Rgb => {
+                self.calc_subdivision_score() - noise_floor > self.config.color_threshold * decay
+            }
+            ColorSpace::YCbCr => {
+                let (luma_variance, chroma_variance) = self.calc_ycbcr_variance();
+                luma_variance - noise_floor > self.config.luma_threshold * decay
+                    || chroma_variance - noise_floor > self.config.chroma_threshold * decay
             }
         }
+    }
 
-        return (color_sum as f64) / (3.0 * self.width as f64 * self.height as f64);
+    /// Whether the depth and size limits allow this quad to be split at all,
+    /// regardless of its color distance.
+    pub fn can_subdivide(&self) -> bool {
+        // Splitting anything narrower than 2 pixels would create empty children
+        let size_threshold = self.config.effective_size_threshold(self.cur_depth).max(1);
+        let large_enough = if self.splits_binary() {
+            self.width.max(self.height) > size_threshold
+        } else {
+            self.width > size_threshold && self.height > size_threshold
+        };
+        self.cur_depth < self.config.effective_max_depth(self.color) && large_enough
     }
 
-    pub fn calc_avg_color(&self) -> Rgba<u8> {
-        let mut total_red: u32 = 0;
-        let mut total_green: u32 = 0;
-        let mut total_blue: u32 = 0;
-        for x in (self.x)..(self.x + self.width) {
-            for y in (self.y)..(self.y + self.height) {
-                if x >= self.image.width() || y >= self.image.height() {
+    /// Whether `split` halves this quad along its longer axis instead of
+    /// splitting it into quadrants.
+    fn splits_binary(&self) -> bool {
+        let (long_side, short_side) = (self.width.max(self.height), self.width.min(self.height));
+        self.config.split_strategy == SplitStrategy::Adaptive
+            && long_side as f64 >= ADAPTIVE_SPLIT_ASPECT_RATIO * short_side as f64
+    }
+
+    /// Splits the quad into children according to `config.split_strategy`.
+    pub fn split(&self) -> Vec<Quad> {
+        if self.splits_binary() {
+            self.subdivide_binary().to_vec()
+        } else {
+            self.subdivide().to_vec()
+        }
+    }
+
+    /// Returns the length of the top or left part when a side of `length` is
+    /// split in two, rounded according to `config.split_rounding`.
+    fn first_half(&self, length: u32) -> u32 {
+        let round_up = match self.config.split_rounding {
+            SplitRounding::Ceil => true,
+            SplitRounding::Floor => false,
+            SplitRounding::Alternate => self.cur_depth.is_multiple_of(2),
+        };
+        if round_up {
+            length.div_ceil(2)
+        } else {
+            length / 2
+        }
+    }
+
+    /// Halves the quad along its longer axis, with the same handling of odd
+    /// sizes as `subdivide`.
+    pub fn subdivide_binary(&self) -> [Quad; 2] {
+        if self.width >= self.height {
+            let left_width = self.first_half(self.width);
+            [
+                self.child(self.x, self.y, left_width, self.height),
+                self.child(
+                    self.x + left_width,
+                    self.y,
+                    self.width - left_width,
+                    self.height,
+                ),
+            ]
+        } else {
+            let top_height = self.first_half(self.height);
+            [
+                self.child(self.x, self.y, self.width, top_height),
+                self.child(
+                    self.x,
+                    self.y + top_height,
+                    self.width,
+                    self.height - top_height,
+                ),
+            ]
+        }
+    }
+
+    fn calc_subdivision_score(&self) -> f64 {
+        let mut score = self.calc_avg_color_distance();
+        if self.config.surround_contrast_weight != 0.0 {
+            score += self.config.surround_contrast_weight * self.calc_surround_contrast();
+        }
+        score
+    }
+
+    /// Mean per-channel difference between the quad's average color and the
+    /// average of the one pixel wide ring just outside it.
+    pub fn calc_surround_contrast(&self) -> f64 {
+        let (image_width, image_height) = self.image.dimensions();
+        let x_start = self.x.saturating_sub(1);
+        let y_start = self.y.saturating_sub(1);
+        let x_end = (self.x + self.width + 1).min(image_width);
+        let y_end = (self.y + self.height + 1).min(image_height);
+
+        let mut totals = [0u64; 3];
+        let mut count = 0u64;
+        for x in x_start..x_end {
+            for y in y_start..y_end {
+                let inside = x >= self.x
+                    && x < self.x + self.width
+                    && y >= self.y
+                    && y < self.y + self.height;
+                if inside {
                     continue;
                 }
-                let pixel = self.image.get_pixel(x, y);
-                let pixel_rgba = pixel.to_rgba();
-                let rgba_arr = pixel_rgba.0;
-                total_red += rgba_arr[0] as u32;
-                total_green += rgba_arr[1] as u32;
-                total_blue += rgba_arr[2] as u32;
+                let rgba_arr = self.image.get_pixel(x, y).0;
+                for channel in 0..3 {
+                    totals[channel] += rgba_arr[channel] as u64;
+                }
+                count += 1;
             }
         }
+        if count == 0 {
+            return 0.0;
+        }
+
+        let avg_color_rgba = self.color.0;
+        let mut contrast = 0.0;
+        for channel in 0..3 {
+            contrast +=
+                (avg_color_rgba[channel] as f64 - totals[channel] as f64 / count as f64).abs();
+        }
+        contrast / 3.0
+    }
+
+    pub fn calc_avg_color_distance(&self) -> f64 {
+        let area = self.width as f64 * self.height as f64;
+        if self.config.channel_focus != ChannelFocus::All {
+            let distance_sum: f64 = self
+                .pixels()
+                .map(|pixel| focus_distance(pixel, self.color, self.config.channel_focus))
+                .sum();
+            return distance_sum / area;
+        }
+        match self.config.threshold_metric {
+            ThresholdMetric::MeanDistance => self.calc_mean_distance(),
+            ThresholdMetric::Variance => self.calc_color_variance(),
+            ThresholdMetric::EdgeMaskedVariance => self
+                .edge_mask
+                .as_ref()
+                .expect("The edge mask is built for the edge-masked variance metric.")
+                .region_variance(self.x, self.y, self.width, self.height)
+                .unwrap_or_else(|| self.calc_color_variance()),
+            ThresholdMetric::EdgeDensity => self
+                .gradients
+                .as_ref()
+                .expect("Gradients are built for the edge density metric.")
+                .region_mean(self.x, self.y, self.width, self.height),
+        }
+    }
+
+    /// Mean distance of the quad's pixels from its average color under
+    /// `config.color_metric`.
+    fn calc_mean_distance(&self) -> f64 {
+        let area = self.width as f64 * self.height as f64;
+        match self.config.color_metric {
+            metric @ (ColorMetric::Rgb | ColorMetric::WeightedRgb) => {
+                let distance_sum: f64 = self
+                    .pixels()
+                    .map(|pixel| color_distance(pixel, self.color, metric))
+                    .sum();
+                distance_sum / area
+            }
+            ColorMetric::CieLab => {
+                let avg_lab = srgb_to_lab(self.color);
+                let distance_sum: f64 = self
+                    .pixels()
+                    .map(|pixel| delta_e76(srgb_to_lab(pixel), avg_lab))
+                    .sum();
+                distance_sum / area
+            }
+        }
+    }
+
+    /// Mean of the R, G and B variances over the quad, weighted by alpha like
+    /// the average color and read in constant time from the sums and sums of
+    /// squares of each channel.
+    fn calc_color_variance(&self) -> f64 {
+        let squares = self
+            .squares
+            .as_ref()
+            .expect("Squares are built for the variance metric.");
+        let [red, green, blue, total_alpha] =
+            squares.region_sum(self.x, self.y, self.width, self.height);
+        let Some(avg) = self.calc_avg_channels() else {
+            return 0.0;
+        };
+        let scale = if self.integral().sixteen_bit {
+            257.0
+        } else {
+            1.0
+        };
+        let alpha = total_alpha as f64;
+        let variance_sum: f64 = [red, green, blue]
+            .into_iter()
+            .zip(avg)
+            .map(|(squared_sum, mean)| {
+                let mean = mean / scale;
+                (squared_sum as f64 / alpha - mean * mean).max(0.0)
+            })
+            .sum();
+        variance_sum / 3.0
+    }
+
+    /// Returns the variance of the luma and the mean variance of Cb and Cr over
+    /// the quad, both in squared channel units.
+    pub fn calc_ycbcr_variance(&self) -> (f64, f64) {
+        let mut count: u64 = 0;
+        let mut sums = [0.0f64; 3];
+        let mut squared_sums = [0.0f64; 3];
+        for pixel in self.pixels() {
+            count += 1;
+            for (channel, value) in rgb_to_ycbcr(pixel).into_iter().enumerate() {
+                sums[channel] += value;
+                squared_sums[channel] += value * value;
+            }
+        }
+        if count == 0 {
+            return (0.0, 0.0);
+        }
+
+        let count = count as f64;
+        let [luma, cb, cr] = [0, 1, 2].map(|channel| {
+            let mean = sums[channel] / count;
+            (squared_sums[channel] / count - mean * mean).max(0.0)
+        });
+        (luma, (cb + cr) / 2.0)
+    }
+
+    /// Iterates over the source pixels covered by the quad, skipping any that
+    /// fall outside the image.
+    fn pixels(&self) -> impl Iterator<Item = Rgba<u8>> + '_ {
+        let x_end = (self.x + self.width).min(self.image.width());
+        let y_end = (self.y + self.height).min(self.image.height());
+        (self.x..x_end).flat_map(move |x| (self.y..y_end).map(move |y| self.image.get_pixel(x, y)))
+    }
+
+    /// Returns the color the quad is drawn with before any post-processing:
+    /// the mean or the median of its pixels, as picked by
+    /// `config.color_aggregation`.
+    pub fn calc_color(&self) -> Rgba<u8> {
+        match self.config.color_aggregation {
+            ColorAggregation::Mean => self.calc_avg_color(),
+            ColorAggregation::Median => self.calc_median_color(),
+        }
+    }
+
+    /// Per-channel median of the quad's pixels, found with a 256-bin histogram
+    /// per channel. Fully transparent pixels don't count towards R, G and B.
+    pub fn calc_median_color(&self) -> Rgba<u8> {
+        let mut histograms = [[0u32; 256]; 4];
+        let mut opaque_count = 0;
+        let mut count = 0;
+        for pixel in self.pixels() {
+            let [r, g, b, a] = pixel.0;
+            count += 1;
+            histograms[3][a as usize] += 1;
+            if a > 0 {
+                opaque_count += 1;
+                histograms[0][r as usize] += 1;
+                histograms[1][g as usize] += 1;
+                histograms[2][b as usize] += 1;
+            }
+        }
+        if opaque_count == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let median = |histogram: &[u32; 256], total: u32| {
+            let mut seen = 0;
+            let middle = total.div_ceil(2);
+            histogram
+                .iter()
+                .position(|bin| {
+                    seen += bin;
+                    seen >= middle
+                })
+                .unwrap_or(255) as u8
+        };
+        Rgba([
+            median(&histograms[0], opaque_count),
+            median(&histograms[1], opaque_count),
+            median(&histograms[2], opaque_count),
+            median(&histograms[3], count),
+        ])
+    }
+
+    /// Average color of the quad, read from the integral image in constant time.
+    /// RGB is weighted by alpha so transparent pixels don't pull the color
+    /// toward whatever they store, and alpha is averaged over the whole quad.
+    pub fn calc_avg_color(&self) -> Rgba<u8> {
+        let Some(avg) = self.calc_avg_channels() else {
+            return Rgba([0, 0, 0, 0]);
+        };
+        if self.integral().sixteen_bit {
+            Rgba(avg.map(|channel| (channel / 257.0) as u8))
+        } else {
+            Rgba(avg.map(|channel| channel as u8))
+        }
+    }
+
+    /// Returns the quad's color at 16 bits per channel. For 16-bit sources with
+    /// the mean aggregation this keeps the full precision of the average;
+    /// otherwise it is the 8-bit color scaled up.
+    pub fn calc_color16(&self) -> Rgba<u16> {
+        if self.config.color_aggregation != ColorAggregation::Mean || !is_sixteen_bit(&self.image) {
+            return Rgba(self.color.0.map(|channel| channel as u16 * 257));
+        }
+        match self.calc_avg_channels() {
+            Some(avg) => Rgba(avg.map(|channel| channel as u16)),
+            None => Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    /// Returns the 16-bit color a leaf is rendered with. Post-processing in
+    /// `config` works at 8 bits, so any of it gives the scaled-up 8-bit color.
+    pub fn fill_color16(&self, config: &QuadConfig) -> Rgba<u16> {
+        let post_processed = config.color_mode != ColorMode::Average
+            || config.saturation_boost != 1.0
+            || config.contrast != 1.0
+            || config.color_jitter > 0
+            || config.posterize_levels.is_some()
+            || config.grayscale
+            || config.palette.is_some();
+        if post_processed {
+            Rgba(
+                self.fill_color(config)
+                    .0
+                    .map(|channel| channel as u16 * 257),
+            )
+        } else {
+            self.calc_color16()
+        }
+    }
+
+    /// Returns the summed-area table of the source, building it on first use.
+    fn integral(&self) -> &IntegralImage {
+        self.integral
+            .get_or_init(|| IntegralImage::new(&self.image))
+    }
+
+    /// Alpha-weighted mean RGB and mean alpha over the quad, in the source's
+    /// channel range, or `None` if the quad is fully transparent.
+    fn calc_avg_channels(&self) -> Option<[f64; 4]> {
+        let [weighted_red, weighted_green, weighted_blue, total_alpha] = self
+            .integral()
+            .region_sum(self.x, self.y, self.width, self.height);
+        if total_alpha == 0 {
+            return None;
+        }
+        let alpha = total_alpha as f64;
+        let avg_red = weighted_red as f64 / alpha;
+        let avg_green = weighted_green as f64 / alpha;
+        let avg_blue = weighted_blue as f64 / alpha;
         let area: f64 = (self.width * self.height).into();
-        let avg_red = total_red as f64 / area;
-        let avg_green = total_green as f64 / area;
-        let avg_blue = total_blue as f64 / area;
-        return Rgba([avg_red as u8, avg_green as u8, avg_blue as u8, 255]);
+        let avg_alpha = alpha / area;
+        Some([avg_red, avg_green, avg_blue, avg_alpha])
     }
 
     pub fn subdivide(&self) -> [Quad; 4] {
-        let new_width: u32 = (self.width as f64 / 2.0).ceil() as u32;
-        let new_height: u32 = (self.height as f64 / 2.0).ceil() as u32;
+        let (left_width, top_height) = (self.first_half(self.width), self.first_half(self.height));
+        let right_width = self.width - left_width;
+        let bottom_height = self.height - top_height;
 
         let x1 = self.x;
-        let x2 = self.x + new_width;
+        let x2 = self.x + left_width;
         let y1 = self.y;
-        let y2 = self.y + new_height;
-        return [
-            Quad::new(
-                self.image.clone(),
-                x1,
-                y1,
-                new_width,
-                new_height,
-                self.max_depth,
-                self.cur_depth + 1,
-            ),
-            Quad::new(
-                self.image.clone(),
-                x2,
-                y1,
-                new_width,
-                new_height,
-                self.max_depth,
-                self.cur_depth + 1,
-            ),
-            Quad::new(
-                self.image.clone(),
-                x1,
-                y2,
-                new_width,
-                new_height,
-                self.max_depth,
-                self.cur_depth + 1,
-            ),
-            Quad::new(
-                self.image.clone(),
-                x2,
-                y2,
-                new_width,
-                new_height,
-                self.max_depth,
-                self.cur_depth + 1,
-            ),
-        ];
+        let y2 = self.y + top_height;
+        [
+            (x1, y1, left_width, top_height),
+            (x2, y1, right_width, top_height),
+            (x1, y2, left_width, bottom_height),
+            (x2, y2, right_width, bottom_height),
+        ]
+        .map(|(x, y, width, height)| self.child(x, y, width, height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::render_to_image;
+    use image::{ImageOutputFormat, RgbaImage};
+    use std::io::Cursor;
+
+    fn encode_png(img: RgbaImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(img)
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn quadrants(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            match (x < width / 2, y < height / 2) {
+                (true, true) => Rgba([255, 0, 0, 255]),
+                (false, true) => Rgba([0, 255, 0, 255]),
+                (true, false) => Rgba([0, 0, 255, 255]),
+                (false, false) => Rgba([255, 255, 255, 255]),
+            }
+        }))
+    }
+
+    fn checkerboard(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn oversized_input_is_downscaled() {
+        let bytes = encode_png(RgbaImage::from_pixel(2000, 500, Rgba([10, 20, 30, 255])));
+        let img = load_image_from_memory(&bytes, Some(800), true).unwrap();
+        assert_eq!((img.width(), img.height()), (800, 200));
+
+        let img = load_image_from_memory(&bytes, Some(2000), true).unwrap();
+        assert_eq!((img.width(), img.height()), (2000, 500));
+    }
+
+    #[test]
+    fn estimate_grows_with_size_and_depth() {
+        let config = QuadConfig::default();
+        let small = estimate_cost(200, 200, &config);
+        let large = estimate_cost(800, 800, &config);
+        assert!(large.pixel_scans > small.pixel_scans);
+        assert!(large.estimated_leaves >= small.estimated_leaves);
+        assert!(large.estimated_duration > small.estimated_duration);
+
+        let shallow = QuadConfig {
+            max_depth: 2,
+            ..config.clone()
+        };
+        let shallow = estimate_cost(800, 800, &shallow);
+        assert!(large.pixel_scans > shallow.pixel_scans);
+        assert!(large.estimated_leaves > shallow.estimated_leaves);
+    }
+
+    #[test]
+    fn size_threshold_shrinks_with_depth() {
+        let config = QuadConfig {
+            max_depth: 6,
+            size_threshold: 2,
+            size_threshold_by_depth: true,
+            ..QuadConfig::default()
+        };
+        assert!(config.effective_size_threshold(0) > config.effective_size_threshold(3));
+        assert!(config.effective_size_threshold(3) > config.effective_size_threshold(6));
+        assert_eq!(config.effective_size_threshold(6), 2);
+
+        // A checkerboard never stops on color, so only the size threshold ends subdivision
+        let quad = Quad::new(checkerboard(128, 128), 0, 0, 128, 128, config.clone(), 0);
+        let leaves = subdivide_nodes(quad);
+        assert!(leaves
+            .iter()
+            .all(|leaf| (leaf.width, leaf.height) == (4, 4)));
+
+        let flat = QuadConfig {
+            size_threshold_by_depth: false,
+            ..config
+        };
+        let quad = Quad::new(checkerboard(128, 128), 0, 0, 128, 128, flat, 0);
+        let leaves = subdivide_nodes(quad);
+        assert!(leaves
+            .iter()
+            .all(|leaf| (leaf.width, leaf.height) == (2, 2)));
+    }
+
+    #[test]
+    fn mostly_red_image_has_reddish_dominant_color() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(200, 100, |x, y| {
+            if x < 140 {
+                Rgba([200 + (y % 8) as u8, 20, 30, 255])
+            } else if y < 50 {
+                Rgba([20, 40, 220, 255])
+            } else {
+                Rgba([30, 210, 40, 255])
+            }
+        }));
+        let [r, g, b, a] = dominant_color(&image).0;
+        assert!(r > 190 && g < 60 && b < 60, "{:?}", [r, g, b]);
+        assert_eq!(a, 255);
+    }
+
+    #[test]
+    fn two_posterize_levels_give_extreme_channels() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+        }));
+        let config = QuadConfig {
+            posterize_levels: Some(2),
+            ..QuadConfig::default()
+        };
+        let leaves = subdivide_nodes(Quad::new(image, 0, 0, 64, 64, config.clone(), 0));
+        assert!(leaves.len() > 1);
+        for leaf in &leaves {
+            let [r, g, b, _] = leaf.fill_color(&config).0;
+            assert!([r, g, b]
+                .iter()
+                .all(|channel| *channel == 0 || *channel == 255));
+        }
+    }
+
+    #[test]
+    fn children_share_the_config_and_leaves_are_unchanged() {
+        let quad = Quad::new(quadrants(16, 16), 0, 0, 16, 16, QuadConfig::default(), 0);
+        for child in quad.subdivide() {
+            assert!(Arc::ptr_eq(&child.config, &quad.config));
+            assert!(Arc::ptr_eq(&child.image, &quad.image));
+        }
+
+        let leaves: Vec<_> = subdivide_nodes(quad)
+            .iter()
+            .map(|leaf| ((leaf.x, leaf.y, leaf.width, leaf.height), leaf.color.0))
+            .collect();
+        assert_eq!(
+            leaves,
+            [
+                ((0, 0, 8, 8), [255, 0, 0, 255]),
+                ((8, 0, 8, 8), [0, 255, 0, 255]),
+                ((0, 8, 8, 8), [0, 0, 255, 255]),
+                ((8, 8, 8, 8), [255, 255, 255, 255]),
+            ]
+        );
+    }
+
+    #[test]
+    fn alternating_split_evens_out_corner_leaves() {
+        let corner_size_difference = |split_rounding| {
+            let config = QuadConfig {
+                max_depth: 4,
+                size_threshold: 1,
+                uniform_grid: true,
+                split_rounding,
+                ..QuadConfig::default()
+            };
+            let leaves = subdivide_nodes(Quad::new(quadrants(100, 100), 0, 0, 100, 100, config, 0));
+            let top_left = leaves
+                .iter()
+                .find(|leaf| leaf.x == 0 && leaf.y == 0)
+                .unwrap();
+            let bottom_right = leaves
+                .iter()
+                .find(|leaf| leaf.x + leaf.width == 100 && leaf.y + leaf.height == 100)
+                .unwrap();
+            top_left.width.abs_diff(bottom_right.width)
+                + top_left.height.abs_diff(bottom_right.height)
+        };
+        assert_eq!(corner_size_difference(SplitRounding::Ceil), 2);
+        assert_eq!(corner_size_difference(SplitRounding::Alternate), 0);
+    }
+
+    #[test]
+    fn surround_contrast_splits_an_isolated_square() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            if (8..16).contains(&x) && (8..16).contains(&y) {
+                Rgba([20, 20, 20, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        }));
+        let square_leaves = |surround_contrast_weight| {
+            let config = QuadConfig {
+                surround_contrast_weight,
+                ..QuadConfig::default()
+            };
+            subdivide_nodes(Quad::new(image.clone(), 0, 0, 32, 32, config, 0))
+                .into_iter()
+                .filter(|leaf| (8..16).contains(&leaf.x) && (8..16).contains(&leaf.y))
+                .map(|leaf| (leaf.x, leaf.y, leaf.width, leaf.height))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(square_leaves(0.0), [(8, 8, 8, 8)]);
+        let split = square_leaves(1.0);
+        assert_eq!(split.len(), 4);
+        assert!(split
+            .iter()
+            .all(|&(_, _, width, height)| (width, height) == (4, 4)));
+    }
+
+    #[test]
+    fn detail_color_ranges_keep_detail_in_range() {
+        // Both halves are fine checkerboards, so only the depth limit stops them
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(128, 64, |x, y| {
+            let offset = if (x + y) % 2 == 0 { 0 } else { 50 };
+            if x < 64 {
+                Rgba([185 + offset, 135 + offset, 105 + offset, 255])
+            } else {
+                Rgba([20 + offset / 2, 40 + offset / 2, 180 + offset / 2, 255])
+            }
+        }));
+        let config = QuadConfig {
+            max_depth: 6,
+            size_threshold: 1,
+            detail_color_ranges: vec![(Rgba([180, 130, 100, 255]), Rgba([240, 190, 160, 255]))],
+            ..QuadConfig::default()
+        };
+        let leaves = subdivide_nodes(Quad::new(image, 0, 0, 128, 64, config, 0));
+        let (inside, outside): (Vec<_>, Vec<_>) = leaves.iter().partition(|leaf| leaf.x < 64);
+        let area = |leaf: &&Quad| leaf.width * leaf.height;
+        assert!(inside.iter().map(area).max() < outside.iter().map(area).min());
+        assert!(inside.iter().all(|leaf| leaf.cur_depth == 6));
+        assert!(outside.iter().all(|leaf| leaf.cur_depth == 3));
+    }
+
+    #[test]
+    fn half_transparent_source_keeps_alpha() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 0])
+            }
+        }));
+        let quad = Quad::new(image, 0, 0, 4, 2, QuadConfig::default(), 0);
+        // The transparent blue doesn't pull in its RGB, only halves the alpha
+        assert_eq!(quad.calc_avg_color(), Rgba([255, 0, 0, 127]));
+
+        let translucent = Rgba([10, 20, 30, 128]);
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, translucent));
+        let config = QuadConfig {
+            outline_color: None,
+            ..QuadConfig::default()
+        };
+        let leaves = subdivide_nodes(Quad::new(image, 0, 0, 4, 4, config.clone(), 0));
+        assert_eq!(leaves[0].color, translucent);
+        let output = crate::render::render_to_image(&leaves, 4, 4, &config);
+        assert!(output.pixels().all(|pixel| *pixel == translucent));
+    }
+
+    #[test]
+    fn leaves_cover_every_pixel_once() {
+        for (width, height) in [(1, 1), (7, 5), (33, 17), (64, 3), (101, 99)] {
+            for split_strategy in [SplitStrategy::Quad, SplitStrategy::Adaptive] {
+                for size_threshold in [1, 2, 5] {
+                    let config = QuadConfig {
+                        size_threshold,
+                        split_strategy,
+                        ..QuadConfig::default()
+                    };
+                    let image = checkerboard(width, height);
+                    let leaves = subdivide_nodes(Quad::new(image, 0, 0, width, height, config, 0));
+                    let area: u32 = leaves.iter().map(|leaf| leaf.width * leaf.height).sum();
+                    assert_eq!(area, width * height);
+
+                    let mut coverage = vec![0u8; (width * height) as usize];
+                    for leaf in &leaves {
+                        for y in leaf.y..leaf.y + leaf.height {
+                            for x in leaf.x..leaf.x + leaf.width {
+                                coverage[(y * width + x) as usize] += 1;
+                            }
+                        }
+                    }
+                    assert!(coverage.iter().all(|count| *count == 1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ycbcr_thresholds_split_luma_and_chroma_separately() {
+        // Gray ramp: luma changes, chroma stays at 128
+        let luma_ramp = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let v = (x * 4) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        // Swaps blue for red with green making up the difference, so luma stays at 100
+        let chroma_ramp = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let (r, b) = ((x * 2) as f64, (126 - x * 2) as f64);
+            let g = (100.0 - 0.299 * r - 0.114 * b) / 0.587;
+            Rgba([r as u8, g.round() as u8, b as u8, 255])
+        }));
+        let luma_only = QuadConfig {
+            color_space: ColorSpace::YCbCr,
+            luma_threshold: 50.0,
+            chroma_threshold: f64::MAX,
+            ..QuadConfig::default()
+        };
+        let chroma_only = QuadConfig {
+            luma_threshold: f64::MAX,
+            chroma_threshold: 50.0,
+            ..luma_only.clone()
+        };
+
+        let quad = |image: &DynamicImage, config: &QuadConfig| {
+            Quad::new(image.clone(), 0, 0, 64, 64, config.clone(), 0)
+        };
+        let (luma, chroma) = quad(&luma_ramp, &luma_only).calc_ycbcr_variance();
+        assert!(luma > 1000.0 && chroma < 1.0, "{} {}", luma, chroma);
+        let (luma, chroma) = quad(&chroma_ramp, &luma_only).calc_ycbcr_variance();
+        assert!(luma < 1.0 && chroma > 200.0, "{} {}", luma, chroma);
+
+        assert!(quad(&luma_ramp, &luma_only).should_subdivide());
+        assert!(!quad(&luma_ramp, &chroma_only).should_subdivide());
+        assert!(quad(&chroma_ramp, &chroma_only).should_subdivide());
+        assert!(!quad(&chroma_ramp, &luma_only).should_subdivide());
+    }
+
+    #[test]
+    fn children_tile_the_parent_under_every_rounding() {
+        for split_rounding in [
+            SplitRounding::Ceil,
+            SplitRounding::Floor,
+            SplitRounding::Alternate,
+        ] {
+            let config = QuadConfig {
+                split_rounding,
+                ..QuadConfig::default()
+            };
+            let root = Quad::new(checkerboard(40, 40), 0, 0, 40, 40, config, 0);
+            for depth in 0..2 {
+                let parent = root.child(3, 5, 13, 7);
+                let parent = Quad {
+                    cur_depth: depth,
+                    ..parent
+                };
+                let children = parent.subdivide();
+                let area: u32 = children
+                    .iter()
+                    .map(|child| child.width * child.height)
+                    .sum();
+                assert_eq!(area, 13 * 7);
+                let [top_left, top_right, bottom_left, bottom_right] = children.map(|c| c.bounds());
+                assert_eq!(top_left.0 + top_left.2, top_right.0);
+                assert_eq!(top_right.0 + top_right.2, 3 + 13);
+                assert_eq!(top_left.1 + top_left.3, bottom_left.1);
+                assert_eq!(bottom_left.1 + bottom_left.3, 5 + 7);
+                assert_eq!(
+                    (bottom_right.0, bottom_right.1),
+                    (top_right.0, bottom_left.1)
+                );
+
+                let expected_left = match (split_rounding, depth) {
+                    (SplitRounding::Ceil, _) | (SplitRounding::Alternate, 0) => 7,
+                    _ => 6,
+                };
+                assert_eq!(top_left.2, expected_left);
+
+                let [left, right] = parent.subdivide_binary().map(|c| c.bounds());
+                assert_eq!(left, (3, 5, expected_left, 7));
+                assert_eq!(right, (3 + expected_left, 5, 13 - expected_left, 7));
+            }
+        }
+    }
+
+    #[test]
+    fn edge_density_splits_on_edges_but_not_smooth_ramps() {
+        let ramp = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let v = (x * 2) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let stripes = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let v = if x / 2 % 2 == 0 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        }));
+        let mean_distance = QuadConfig {
+            color_threshold: 20.0,
+            ..QuadConfig::default()
+        };
+        let edge_density = QuadConfig {
+            threshold_metric: ThresholdMetric::EdgeDensity,
+            ..mean_distance.clone()
+        };
+        let quad = |image: &DynamicImage, config: &QuadConfig| {
+            Quad::new(image.clone(), 0, 0, 64, 64, config.clone(), 0)
+        };
+
+        // The ramp spans a wide range of colors but has no edges
+        assert!(quad(&ramp, &mean_distance).should_subdivide());
+        let ramp_density = quad(&ramp, &edge_density).calc_avg_color_distance();
+        assert!(ramp_density < 20.0, "{}", ramp_density);
+        assert!(!quad(&ramp, &edge_density).should_subdivide());
+
+        let stripe_density = quad(&stripes, &edge_density).calc_avg_color_distance();
+        assert!(stripe_density > 100.0, "{}", stripe_density);
+        assert!(quad(&stripes, &edge_density).should_subdivide());
+    }
+
+    #[test]
+    fn variance_from_squares_matches_a_pixel_scan() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(37, 23, |x, y| {
+            Rgba([(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8, 255])
+        }));
+        let config = QuadConfig {
+            threshold_metric: ThresholdMetric::Variance,
+            ..QuadConfig::default()
+        };
+        for (x, y, width, height) in [(0, 0, 37, 23), (5, 3, 20, 9), (36, 22, 1, 1)] {
+            let quad = Quad::new(image.clone(), x, y, width, height, config.clone(), 0);
+            let count = (width * height) as f64;
+            let expected = (0..3)
+                .map(|channel| {
+                    let values: Vec<f64> = quad.pixels().map(|p| p.0[channel] as f64).collect();
+                    let mean = values.iter().sum::<f64>() / count;
+                    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / count
+                })
+                .sum::<f64>()
+                / 3.0;
+            let variance = quad.calc_avg_color_distance();
+            assert!(
+                (variance - expected).abs() < 1e-6,
+                "{} {}",
+                variance,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn integral_image_is_only_built_when_a_mean_is_read() {
+        let median = QuadConfig {
+            color_aggregation: ColorAggregation::Median,
+            ..QuadConfig::default()
+        };
+        let root = Quad::new(quadrants(16, 16), 0, 0, 16, 16, median, 0);
+        subdivide_nodes(root.clone());
+        assert!(root.integral.get().is_none());
+
+        let root = Quad::new(quadrants(16, 16), 0, 0, 16, 16, QuadConfig::default(), 0);
+        assert!(root.integral.get().is_some());
+    }
+
+    #[test]
+    fn edge_masked_variance_splits_on_an_edge_but_not_on_noise() {
+        // The same low-amplitude noise on both, plus a hard vertical edge on one
+        let noise = |x: u32, y: u32| ((x * 7 + y * 13) % 9) as u8;
+        let noisy = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            let v = 124 + noise(x, y);
+            Rgba([v, v, v, 255])
+        }));
+        let edged = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            let v = if x < 16 { 40 } else { 200 } + noise(x, y);
+            Rgba([v, v, v, 255])
+        }));
+        let config = QuadConfig {
+            threshold_metric: ThresholdMetric::EdgeMaskedVariance,
+            color_threshold: 50.0,
+            ..QuadConfig::default()
+        };
+        let quad = |image: &DynamicImage| Quad::new(image.clone(), 0, 0, 32, 32, config.clone(), 0);
+
+        let flat = quad(&noisy);
+        assert!(flat
+            .edge_mask
+            .as_ref()
+            .unwrap()
+            .region_variance(0, 0, 32, 32)
+            .is_none());
+        assert!(flat.calc_avg_color_distance() < 50.0);
+        assert!(!flat.should_subdivide());
+
+        let edge = quad(&edged);
+        assert!(edge.calc_avg_color_distance() > 1000.0);
+        assert!(edge.should_subdivide());
+        // A quad well away from the edge only sees the noise
+        assert!(!edge.child(0, 0, 8, 32).should_subdivide());
+    }
+
+    #[test]
+    fn area_exponent_changes_which_quad_splits_first() {
+        // A small checkerboard patch in a mildly striped top-left quadrant, and
+        // more strongly striped quadrants elsewhere
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            let v = if x < 16 && y < 16 {
+                if (x + y) % 2 == 0 {
+                    0
+                } else {
+                    255
+                }
+            } else if x < 32 && y < 32 {
+                if x % 2 == 0 {
+                    118
+                } else {
+                    138
+                }
+            } else if x % 2 == 0 {
+                92
+            } else {
+                164
+            };
+            Rgba([v, v, v, 255])
+        }));
+        // The root, then the top-left quadrant, then one more split
+        let leaves = |priority_area_exponent: f64| {
+            let config = QuadConfig {
+                priority_area_exponent,
+                ..QuadConfig::default()
+            };
+            let mut bounds: Vec<_> =
+                subdivide_to_count(Quad::new(image.clone(), 0, 0, 64, 64, config, 0), 10)
+                    .iter()
+                    .map(Quad::bounds)
+                    .collect();
+            bounds.sort();
+            bounds
+        };
+        let by_error = leaves(0.0);
+        let by_weighted_error = leaves(1.0);
+        assert_ne!(by_error, by_weighted_error);
+        // Ignoring size, the small checkerboard is split next...
+        assert!(by_error.contains(&(0, 0, 8, 8)));
+        assert!(by_error.contains(&(32, 0, 32, 32)));
+        // ...while weighting by area, a larger striped quadrant is
+        assert!(by_weighted_error.contains(&(0, 0, 16, 16)));
+        assert!(by_weighted_error.contains(&(32, 0, 16, 16)));
+    }
+
+    #[test]
+    fn stats_of_fully_skipped_image_are_zero() {
+        let config = QuadConfig {
+            skip_transparent: true,
+            ..QuadConfig::default()
+        };
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 0])));
+        let (leaves, stats) = subdivide_with_stats(Quad::new(image, 0, 0, 8, 8, config, 0));
+        assert!(leaves.is_empty());
+        assert_eq!(stats.leaf_count, 0);
+        assert_eq!(stats.min_leaf_size, 0);
+        assert_eq!(stats.avg_leaf_size, 0.0);
+    }
+
+    #[test]
+    fn thin_inputs_give_one_leaf() {
+        for (width, height) in [(1, 1), (1, 16), (16, 1)] {
+            let image = RgbaImage::from_fn(width, height, |x, y| {
+                Rgba([(x * 15) as u8, (y * 15) as u8, 200, 255])
+            });
+            let img = load_image_from_memory(&encode_png(image), None, true).unwrap();
+            let quad = Quad::new(img, 0, 0, width, height, QuadConfig::default(), 0);
+            let average = quad.calc_avg_color();
+            let leaves = subdivide_nodes(quad);
+            assert_eq!(leaves.len(), 1, "{}x{}", width, height);
+            assert_eq!(leaves[0].bounds(), (0, 0, width, height));
+            assert_eq!(leaves[0].color(), average);
+            let output = render_to_image(&leaves, width, height, &QuadConfig::default());
+            assert_eq!(output.dimensions(), (width, height));
+        }
+        for (width, height) in [(0, 16), (16, 0)] {
+            assert!(matches!(
+                prepare_image(DynamicImage::new_rgba8(width, height), &[], None, true),
+                Err(QuadError::EmptyImage)
+            ));
+        }
+    }
+
+    #[test]
+    fn progress_reports_every_interval_and_at_the_end() {
+        let config = QuadConfig {
+            size_threshold: 1,
+            ..QuadConfig::default()
+        };
+        let quad = Quad::new(checkerboard(128, 128), 0, 0, 128, 128, config, 0);
+        let reports = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reports);
+        let leaves: Vec<Quad> = subdivide_iter(quad.clone())
+            .on_progress(move |count| sink.lock().unwrap().push(count))
+            .collect();
+        assert!(leaves.len() > 2 * PROGRESS_INTERVAL);
+        assert_eq!(leaves.len(), subdivide_nodes(quad).len());
+
+        let mut expected: Vec<usize> = (1..=leaves.len() / PROGRESS_INTERVAL)
+            .map(|step| step * PROGRESS_INTERVAL)
+            .collect();
+        expected.push(leaves.len());
+        assert_eq!(*reports.lock().unwrap(), expected);
     }
 }
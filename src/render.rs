@@ -0,0 +1,1501 @@
+use color::{Deg, Hsv, Rgb, ToRgb};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::{WebPEncoder, WebPQuality};
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, ImageBuffer, ImageOutputFormat, Pixel, Rgba, RgbaImage};
+use imageproc::drawing::{
+    draw_filled_circle_mut, draw_filled_ellipse_mut, draw_filled_rect_mut, Canvas,
+};
+use imageproc::rect::Rect;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+
+use crate::config::{FillMode, OutputOptions, QuadConfig, ShapeMode, Symmetry};
+use crate::dither::{dither_leaves, without_color_processing};
+use crate::error::QuadError;
+use crate::quad::{Quad, QuadView};
+use crate::svg::render_svg;
+
+/// RGBA image with 16 bits per channel.
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+/// Renders the leaves and saves them to `output.output_file`. A `.svg`
+/// extension writes vector output; anything else is saved as a raster image
+/// in the format implied by the extension, with `output.jpeg_quality` for JPEG.
+/// WebP is lossy if `output.webp_quality` is set and lossless otherwise. 8-bit
+/// PNG is streamed to the file with `write_png_streaming`.
+pub fn generate_image(
+    quadtree_leaves: Vec<Quad>,
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+    output: &OutputOptions,
+) -> Result<(), QuadError> {
+    if image_width == 0 || image_height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let extension = Path::new(&output.output_file)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("svg") => {
+            let svg = render_svg(&quadtree_leaves, image_width, image_height, config);
+            fs::write(&output.output_file, svg)?;
+        }
+        _ if config.output_bit_depth == 16 => {
+            render_to_image16(&quadtree_leaves, image_width, image_height, config)
+                .save(&output.output_file)?;
+        }
+        Some("jpg") | Some("jpeg") => {
+            let output_image = render_to_image(&quadtree_leaves, image_width, image_height, config);
+            let writer = BufWriter::new(File::create(&output.output_file)?);
+            JpegEncoder::new_with_quality(writer, output.jpeg_quality)
+                .encode_image(&output_image)?;
+        }
+        Some("webp") => {
+            let output_image = render_to_image(&quadtree_leaves, image_width, image_height, config);
+            let writer = BufWriter::new(File::create(&output.output_file)?);
+            write_webp(&output_image, output.webp_quality, writer)?;
+        }
+        Some("png") => {
+            let mut writer = BufWriter::new(File::create(&output.output_file)?);
+            write_png_streaming(
+                &quadtree_leaves,
+                image_width,
+                image_height,
+                config,
+                &mut writer,
+            )?;
+            writer.flush()?;
+        }
+        _ => {
+            let output_image = render_to_image(&quadtree_leaves, image_width, image_height, config);
+            output_image.save(&output.output_file)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes `image` as WebP with libwebp, lossy at `quality` (0 to 100) if one
+/// is given and lossless otherwise.
+pub fn write_webp<W: Write>(
+    image: &RgbaImage,
+    quality: Option<u8>,
+    writer: W,
+) -> Result<(), QuadError> {
+    let quality = quality.map_or_else(WebPQuality::lossless, WebPQuality::lossy);
+    WebPEncoder::new_with_quality(writer, quality).encode(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        ColorType::Rgba8,
+    )?;
+    Ok(())
+}
+
+/// Rows of output rendered at a time by `write_png_streaming`.
+const STREAM_BAND_ROWS: u32 = 64;
+
+/// Same as `render_to_image`, but encodes the output to `writer` as an 8-bit
+/// PNG one band of rows at a time, so only the leaves and one band of pixels
+/// are held in memory instead of the whole canvas plus the encoder's copy of
+/// it. Edge feathering, vertical mirroring and `FillMode::Source` need pixels
+/// outside the band being drawn, so with those the image is rendered in full
+/// first and then encoded.
+pub fn write_png_streaming<W: Write>(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+    writer: W,
+) -> Result<(), QuadError> {
+    if image_width == 0 || image_height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let mut encoder = png::Encoder::new(writer, image_width, image_height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header()?;
+    let mut stream = png_writer.stream_writer()?;
+
+    let needs_whole_canvas = config.edge_feather > 0
+        || config.fill_mode == FillMode::Source
+        || config.symmetry.is_some_and(Symmetry::mirrors_vertically);
+    if needs_whole_canvas {
+        let output_image = render_to_image(quadtree_leaves, image_width, image_height, config);
+        stream.write_all(output_image.as_raw())?;
+    } else {
+        let (views, config) = if config.dithers_palette() {
+            (
+                dither_leaves(quadtree_leaves, config),
+                without_color_processing(config),
+            )
+        } else {
+            (
+                quadtree_leaves.iter().map(Quad::view).collect(),
+                config.clone(),
+            )
+        };
+        let mut views: Vec<QuadView> = views
+            .into_iter()
+            .map(|leaf| to_output_space(leaf, &config))
+            .collect();
+        views.sort_by_key(|leaf| (leaf.y, leaf.x));
+
+        // Rows above and below a leaf that its outline can reach
+        let reach = |leaf: &QuadView| match config.outline_color {
+            Some(_) => config.effective_outline_thickness(leaf.cur_depth),
+            None => 0,
+        };
+        let max_reach = views.iter().map(reach).max().unwrap_or(0);
+        let mut next_leaf = 0;
+        let mut band_leaves: Vec<&QuadView> = Vec::new();
+        for band_y in (0..image_height).step_by(STREAM_BAND_ROWS as usize) {
+            let band_end = (band_y + STREAM_BAND_ROWS).min(image_height);
+            while next_leaf < views.len() && views[next_leaf].y < band_end + max_reach {
+                band_leaves.push(&views[next_leaf]);
+                next_leaf += 1;
+            }
+            band_leaves.retain(|leaf| leaf.y + leaf.height + reach(leaf) >= band_y);
+
+            let mut band = CanvasBand {
+                image: RgbaImage::from_pixel(
+                    image_width,
+                    band_end - band_y,
+                    config.background_color,
+                ),
+                top: band_y,
+                canvas_height: image_height,
+            };
+            for leaf in &band_leaves {
+                fill_view(&mut band, leaf, &config);
+            }
+            if let Some(outline_color) = config.outline_color {
+                draw_outlines(
+                    &mut band,
+                    band_leaves.iter().copied(),
+                    outline_color,
+                    &config,
+                    |color| color,
+                );
+            }
+            if let Some(symmetry) = config.symmetry {
+                mirror_halves(&mut band.image, symmetry);
+            }
+            stream.write_all(band.image.as_raw())?;
+        }
+    }
+    stream.finish()?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+/// Rows `top..top + image.height()` of a canvas `canvas_height` rows tall.
+/// Drawing uses canvas coordinates and pixels outside the band are dropped, so
+/// leaves straddling the band and their canvas-edge checks work unchanged.
+/// Only pixels inside the band can be read.
+struct CanvasBand {
+    image: RgbaImage,
+    top: u32,
+    canvas_height: u32,
+}
+
+impl Canvas for CanvasBand {
+    type Pixel = Rgba<u8>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.image.width(), self.canvas_height)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Self::Pixel {
+        *self.image.get_pixel(x, y - self.top)
+    }
+
+    fn draw_pixel(&mut self, x: u32, y: u32, color: Self::Pixel) {
+        if y >= self.top && y - self.top < self.image.height() {
+            self.image.put_pixel(x, y - self.top, color);
+        }
+    }
+}
+
+/// Renders the leaves and writes them to `writer` encoded in `format`, for
+/// destinations without a file extension to infer the format from, like stdout.
+pub fn write_image(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+    writer: &mut impl Write,
+    format: ImageOutputFormat,
+) -> Result<(), QuadError> {
+    if image_width == 0 || image_height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    // Encoders need to seek, which stdout can't, so encode in memory first
+    let mut encoded: Vec<u8> = Vec::new();
+    let mut cursor = Cursor::new(&mut encoded);
+    if config.output_bit_depth == 16 {
+        render_to_image16(quadtree_leaves, image_width, image_height, config)
+            .write_to(&mut cursor, format)?;
+    } else {
+        render_to_image(quadtree_leaves, image_width, image_height, config)
+            .write_to(&mut cursor, format)?;
+    }
+    writer.write_all(&encoded)?;
+    Ok(())
+}
+
+/// Width in pixels of the divider between the two halves of `render_comparison`.
+const COMPARISON_DIVIDER_WIDTH: u32 = 4;
+
+/// Saves the output of `render_comparison` to `file_path`.
+pub fn export_comparison(
+    original: &DynamicImage,
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    file_path: &str,
+    config: &QuadConfig,
+) -> Result<(), QuadError> {
+    render_comparison(original, quadtree_leaves, image_width, image_height, config)
+        .save(file_path)?;
+    Ok(())
+}
+
+/// Renders the original image and the art side by side, separated by a thin
+/// white divider. The original is resized to the art's height if needed.
+pub fn render_comparison(
+    original: &DynamicImage,
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> RgbaImage {
+    let art = render_to_image(quadtree_leaves, image_width, image_height, config);
+    let original = if original.height() == image_height {
+        original.to_rgba8()
+    } else {
+        let scaled_width = (original.width() as f64 * image_height as f64
+            / original.height() as f64)
+            .round() as u32;
+        original
+            .resize_exact(scaled_width, image_height, FilterType::Lanczos3)
+            .to_rgba8()
+    };
+
+    let mut comparison = RgbaImage::from_pixel(
+        original.width() + COMPARISON_DIVIDER_WIDTH + art.width(),
+        image_height,
+        Rgba([255, 255, 255, 255]),
+    );
+    image::imageops::replace(&mut comparison, &original, 0, 0);
+    let art_x = (original.width() + COMPARISON_DIVIDER_WIDTH) as i64;
+    image::imageops::replace(&mut comparison, &art, art_x, 0);
+    comparison
+}
+
+/// Byte order of each pixel in a raw buffer returned by `render_raw`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+    Rgba,
+    Bgra,
+}
+
+/// Renders the leaves into tightly packed 4-byte pixels in the given layout,
+/// ready for a texture upload without a PNG encode/decode round trip.
+pub fn render_raw(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    layout: PixelLayout,
+    config: &QuadConfig,
+) -> Vec<u8> {
+    let mut raw = render_to_image(quadtree_leaves, image_width, image_height, config).into_raw();
+    if layout == PixelLayout::Bgra {
+        for pixel in raw.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    raw
+}
+
+/// Renders the leaves into the `image_width` x `image_height` region of a larger
+/// canvas starting at the given offset, clipped to the canvas bounds. Pixels
+/// outside that region are left untouched.
+pub fn render_at(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    canvas: &mut RgbaImage,
+    offset_x: u32,
+    offset_y: u32,
+    config: &QuadConfig,
+) {
+    let output_image = render_to_image(quadtree_leaves, image_width, image_height, config);
+    image::imageops::replace(canvas, &output_image, offset_x as i64, offset_y as i64);
+}
+
+/// Renders the leaves to PNG in memory and returns it as a base64 `data:` URI
+/// for embedding in HTML or JSON.
+pub fn render_data_uri(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> Result<String, QuadError> {
+    if image_width == 0 || image_height == 0 {
+        return Err(QuadError::EmptyImage);
+    }
+    let output_image = render_to_image(quadtree_leaves, image_width, image_height, config);
+    let mut png_bytes: Vec<u8> = Vec::new();
+    output_image.write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64_encode(&png_bytes)
+    ))
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Lays `images` out left to right and top to bottom in a grid `columns` wide,
+/// each centered in a cell as large as the largest of them, over `background`.
+pub fn contact_sheet(images: &[RgbaImage], columns: u32, background: Rgba<u8>) -> RgbaImage {
+    let columns = columns.clamp(1, (images.len() as u32).max(1));
+    let rows = (images.len() as u32).div_ceil(columns);
+    let cell_width = images.iter().map(RgbaImage::width).max().unwrap_or(0);
+    let cell_height = images.iter().map(RgbaImage::height).max().unwrap_or(0);
+    let mut sheet = RgbaImage::from_pixel(cell_width * columns, cell_height * rows, background);
+    for (index, image) in images.iter().enumerate() {
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        let x = column * cell_width + (cell_width - image.width()) / 2;
+        let y = row * cell_height + (cell_height - image.height()) / 2;
+        imageops::replace(&mut sheet, image, x as i64, y as i64);
+    }
+    sheet
+}
+
+/// Renders the leaves into an in-memory image using the styling in `config`.
+pub fn render_to_image(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> RgbaImage {
+    if config.fill_mode == FillMode::Source {
+        return render_stream_to_image(
+            quadtree_leaves.iter().cloned(),
+            image_width,
+            image_height,
+            config,
+        );
+    }
+    if config.dithers_palette() {
+        let views = dither_leaves(quadtree_leaves, config);
+        return render_views_to_image(
+            &views,
+            image_width,
+            image_height,
+            &without_color_processing(config),
+        );
+    }
+    let views: Vec<QuadView> = quadtree_leaves.iter().map(Quad::view).collect();
+    render_views_to_image(&views, image_width, image_height, config)
+}
+
+/// Same as `render_to_image`, but for leaves without a source image, such as
+/// ones loaded with `import_quadtree`.
+pub fn render_views_to_image(
+    quadtree_leaves: &[QuadView],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> RgbaImage {
+    let mut output_image =
+        RgbaImage::from_pixel(image_width, image_height, config.background_color);
+    let quadtree_leaves: Vec<QuadView> = quadtree_leaves
+        .iter()
+        .map(|leaf| to_output_space(*leaf, config))
+        .collect();
+
+    fill_leaves(&mut output_image, &quadtree_leaves, config);
+    finish_leaves(&mut output_image, &quadtree_leaves, config);
+    if let Some(symmetry) = config.symmetry {
+        mirror_halves(&mut output_image, symmetry);
+    }
+    output_image
+}
+
+/// Feathers and outlines leaves that have already been filled.
+fn finish_leaves(output_image: &mut RgbaImage, quadtree_leaves: &[QuadView], config: &QuadConfig) {
+    if config.edge_feather > 0 {
+        let flat_image = output_image.clone();
+        for leaf in quadtree_leaves {
+            feather_quad_edges(output_image, &flat_image, leaf, config.edge_feather);
+        }
+    }
+    if let Some(outline_color) = config.outline_color {
+        draw_outlines(
+            output_image,
+            quadtree_leaves,
+            outline_color,
+            config,
+            |color| color,
+        );
+    }
+}
+
+/// Same as `render_to_image`, but fills each leaf as soon as `quadtree_leaves`
+/// yields it, so it can be fed straight from `subdivide_iter` without
+/// collecting the leaves first. Only when outlines or feathering are enabled are
+/// the leaves' views kept around for the passes that run after filling. Palette
+/// dithering needs every leaf up front, so it is not applied.
+pub fn render_stream_to_image(
+    quadtree_leaves: impl IntoIterator<Item = Quad>,
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> RgbaImage {
+    let mut output_image =
+        RgbaImage::from_pixel(image_width, image_height, config.background_color);
+    let keep_views = config.outline_color.is_some() || config.edge_feather > 0;
+    let mut views: Vec<QuadView> = Vec::new();
+    for leaf in quadtree_leaves {
+        let view = to_output_space(leaf.view(), config);
+        if config.fill_mode == FillMode::Source {
+            let source = leaf.image.crop_imm(leaf.x, leaf.y, leaf.width, leaf.height);
+            imageops::replace(
+                &mut output_image,
+                &source.to_rgba8(),
+                view.x as i64,
+                view.y as i64,
+            );
+        } else {
+            fill_view(&mut output_image, &view, config);
+        }
+        if keep_views {
+            views.push(view);
+        }
+    }
+    finish_leaves(&mut output_image, &views, config);
+    if let Some(symmetry) = config.symmetry {
+        mirror_halves(&mut output_image, symmetry);
+    }
+    output_image
+}
+
+#[cfg(not(feature = "parallel"))]
+fn fill_leaves(output_image: &mut RgbaImage, quadtree_leaves: &[QuadView], config: &QuadConfig) {
+    for leaf in quadtree_leaves {
+        fill_view(output_image, leaf, config);
+    }
+}
+
+/// Rows of the output filled by each task of the parallel `fill_leaves`.
+#[cfg(feature = "parallel")]
+const FILL_BAND_ROWS: u32 = 32;
+
+/// Fills rectangular leaves straight into the pixel buffer, splitting it into
+/// bands of rows that are filled in parallel. Leaves never overlap, so the
+/// result is the same as filling them one by one. Other shapes, gradient
+/// fills and shape layers are drawn serially.
+#[cfg(feature = "parallel")]
+fn fill_leaves(output_image: &mut RgbaImage, quadtree_leaves: &[QuadView], config: &QuadConfig) {
+    use rayon::prelude::*;
+
+    if config.shape_mode != ShapeMode::Rectangle
+        || config.fill_mode != FillMode::Flat
+        || !config.shape_layers.is_empty()
+    {
+        for leaf in quadtree_leaves {
+            fill_view(output_image, leaf, config);
+        }
+        return;
+    }
+
+    let (canvas_width, canvas_height) = output_image.dimensions();
+    if canvas_width == 0 || canvas_height == 0 {
+        return;
+    }
+    let mut band_leaves: Vec<Vec<&QuadView>> =
+        vec![Vec::new(); canvas_height.div_ceil(FILL_BAND_ROWS) as usize];
+    for leaf in quadtree_leaves {
+        if leaf.x >= canvas_width || leaf.y >= canvas_height || leaf.width == 0 || leaf.height == 0
+        {
+            continue;
+        }
+        let last_row = (leaf.y + leaf.height).min(canvas_height) - 1;
+        for band in leaf.y / FILL_BAND_ROWS..=last_row / FILL_BAND_ROWS {
+            band_leaves[band as usize].push(leaf);
+        }
+    }
+
+    let row_len = canvas_width as usize * 4;
+    output_image
+        .par_chunks_mut(row_len * FILL_BAND_ROWS as usize)
+        .zip(band_leaves.par_iter())
+        .enumerate()
+        .for_each(|(band_index, (band, leaves))| {
+            let band_y = band_index as u32 * FILL_BAND_ROWS;
+            let band_end = band_y + (band.len() / row_len) as u32;
+            for leaf in leaves {
+                let color = leaf.fill_color(config).0;
+                let x_start = leaf.x as usize * 4;
+                let x_end = (leaf.x + leaf.width).min(canvas_width) as usize * 4;
+                for y in leaf.y.max(band_y)..(leaf.y + leaf.height).min(band_end) {
+                    let row_start = (y - band_y) as usize * row_len;
+                    for pixel in band[row_start + x_start..row_start + x_end].chunks_exact_mut(4) {
+                        pixel.copy_from_slice(&color);
+                    }
+                }
+            }
+        });
+}
+
+/// Same as `render_to_image`, but at 16 bits per channel, so leaves of 16-bit
+/// sources keep the full precision of their average color. Edge feathering is
+/// not applied.
+pub fn render_to_image16(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> Rgba16Image {
+    let widen = |color: Rgba<u8>| Rgba(color.0.map(|channel| channel as u16 * 257));
+    // Palette colors are 8-bit anyway
+    if config.dithers_palette() {
+        let output_image = render_to_image(quadtree_leaves, image_width, image_height, config);
+        return Rgba16Image::from_fn(image_width, image_height, |x, y| {
+            widen(*output_image.get_pixel(x, y))
+        });
+    }
+    let mut output_image =
+        Rgba16Image::from_pixel(image_width, image_height, widen(config.background_color));
+    for leaf in quadtree_leaves {
+        if config.fill_mode == FillMode::Wireframe {
+            continue;
+        }
+        let view = to_output_space(leaf.view(), config);
+        if config.fill_mode == FillMode::Source {
+            let source = leaf.image.crop_imm(leaf.x, leaf.y, leaf.width, leaf.height);
+            imageops::replace(
+                &mut output_image,
+                &source.to_rgba16(),
+                view.x as i64,
+                view.y as i64,
+            );
+            continue;
+        }
+        match view.corner_fill_colors(config) {
+            Some(corners) if config.shape_mode == ShapeMode::Rectangle => {
+                let corners = corners.map(|corner| corner.0.map(|channel| channel as f64 * 257.0));
+                fill_quad_gradient(&mut output_image, &view, corners, |color| {
+                    Rgba(color.map(|channel| channel.round() as u16))
+                });
+            }
+            _ => fill_quad_with_color(
+                &mut output_image,
+                &view,
+                leaf.fill_color16(config),
+                config.shape_mode,
+            ),
+        }
+        draw_shape_layers(
+            &mut output_image,
+            &view,
+            leaf.fill_color(config),
+            config,
+            widen,
+        );
+    }
+    if let Some(outline_color) = config.outline_color {
+        let views: Vec<QuadView> = quadtree_leaves
+            .iter()
+            .map(|leaf| to_output_space(leaf.view(), config))
+            .collect();
+        draw_outlines(&mut output_image, &views, outline_color, config, widen);
+    }
+    if let Some(symmetry) = config.symmetry {
+        mirror_halves(&mut output_image, symmetry);
+    }
+    output_image
+}
+
+/// Overwrites the right and/or bottom half of the image with a reflection of
+/// the opposite half, as `symmetry` asks. With an odd size the middle row or
+/// column is its own reflection and is left as is.
+fn mirror_halves<P: Pixel>(
+    output_image: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    symmetry: Symmetry,
+) {
+    let (width, height) = output_image.dimensions();
+    if symmetry.mirrors_horizontally() {
+        for y in 0..height {
+            for x in width.div_ceil(2)..width {
+                let pixel = *output_image.get_pixel(width - 1 - x, y);
+                output_image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+    if symmetry.mirrors_vertically() {
+        for y in height.div_ceil(2)..height {
+            for x in 0..width {
+                let pixel = *output_image.get_pixel(x, height - 1 - y);
+                output_image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+}
+
+/// Shifts a leaf from source coordinates to output coordinates, relative to
+/// the corner of `config.crop`.
+pub(crate) fn to_output_space(leaf: QuadView, config: &QuadConfig) -> QuadView {
+    let (origin_x, origin_y) = config.crop_origin();
+    QuadView {
+        x: leaf.x - origin_x,
+        y: leaf.y - origin_y,
+        ..leaf
+    }
+}
+
+/// Blends pixels within `edge_feather` of the quad's boundary toward the flat
+/// color just across the nearest edge. Both sides of a boundary meet halfway,
+/// so the transition is symmetric and cell centers keep their flat color.
+fn feather_quad_edges(
+    output_image: &mut RgbaImage,
+    flat_image: &RgbaImage,
+    quad: &QuadView,
+    feather: u32,
+) {
+    if feather == 0 {
+        return;
+    }
+    let (canvas_width, canvas_height) = flat_image.dimensions();
+    let x_end = (quad.x + quad.width).min(canvas_width);
+    let y_end = (quad.y + quad.height).min(canvas_height);
+
+    for x in quad.x..x_end {
+        for y in quad.y..y_end {
+            // Distance to each edge, paired with the pixel just across it
+            let edges = [
+                (x - quad.x, quad.x.checked_sub(1).map(|left| (left, y))),
+                (x_end - 1 - x, Some((quad.x + quad.width, y))),
+                (y - quad.y, quad.y.checked_sub(1).map(|top| (x, top))),
+                (y_end - 1 - y, Some((x, quad.y + quad.height))),
+            ];
+            let nearest_edge = edges
+                .iter()
+                .filter_map(|(distance, neighbor)| {
+                    neighbor
+                        .filter(|(nx, ny)| *nx < canvas_width && *ny < canvas_height)
+                        .map(|neighbor| (*distance, neighbor))
+                })
+                .min_by_key(|(distance, _)| *distance);
+
+            if let Some((distance, (nx, ny))) = nearest_edge {
+                if distance >= feather {
+                    continue;
+                }
+                let t = 0.5 * (1.0 - distance as f64 / feather as f64);
+                let own_color = flat_image.get_pixel(x, y).0;
+                let neighbor_color = flat_image.get_pixel(nx, ny).0;
+                let mut blended = [0u8; 4];
+                for channel in 0..4 {
+                    blended[channel] = (own_color[channel] as f64 * (1.0 - t)
+                        + neighbor_color[channel] as f64 * t)
+                        .round() as u8;
+                }
+                output_image.put_pixel(x, y, Rgba(blended));
+            }
+        }
+    }
+}
+
+/// Renders each leaf in a distinct bright color derived from its position and
+/// depth, so neighboring cells and region identity are easy to tell apart.
+pub fn render_region_ids(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> RgbaImage {
+    let mut output_image =
+        RgbaImage::from_pixel(image_width, image_height, config.background_color);
+    for leaf in quadtree_leaves {
+        let color = region_id_color(leaf.x, leaf.y, leaf.cur_depth);
+        fill_quad_with_color(
+            &mut output_image,
+            &to_output_space(leaf.view(), config),
+            color,
+            config.shape_mode,
+        );
+    }
+    output_image
+}
+
+fn region_id_color(x: u32, y: u32, depth: u32) -> Rgba<u8> {
+    // FNV-1a keeps the color stable across runs and platforms
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for value in [x, y, depth] {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    let hue = (hash % 360) as f32;
+    let saturation = 0.6 + ((hash >> 16) % 40) as f32 / 100.0;
+    let hsv: Hsv<f32> = Hsv::new(Deg(hue), saturation, 1.0);
+    let rgb: Rgb<u8> = hsv.to_rgb();
+    Rgba([rgb.r, rgb.g, rgb.b, 255])
+}
+
+/// Fills a leaf with a gradient between its corner colors if it has them and is
+/// drawn as a rectangle, and with its flat fill color otherwise, then draws
+/// any shape layers over it.
+fn fill_view<C: Canvas<Pixel = Rgba<u8>>>(
+    output_image: &mut C,
+    leaf: &QuadView,
+    config: &QuadConfig,
+) {
+    if config.fill_mode == FillMode::Wireframe {
+        return;
+    }
+    match leaf.corner_fill_colors(config) {
+        Some(corners) if config.shape_mode == ShapeMode::Rectangle => {
+            let corners = corners.map(|corner| corner.0.map(f64::from));
+            fill_quad_gradient(output_image, leaf, corners, |color| {
+                Rgba(color.map(|channel| channel.round() as u8))
+            });
+        }
+        _ => fill_quad_with_color(
+            output_image,
+            leaf,
+            leaf.fill_color(config),
+            config.shape_mode,
+        ),
+    }
+    draw_shape_layers(
+        output_image,
+        leaf,
+        leaf.fill_color(config),
+        config,
+        |color| color,
+    );
+}
+
+/// Draws `config.shape_layers` over a leaf filled with `fill`, each centered in
+/// the leaf and scaled down by its `scale`.
+fn draw_shape_layers<C: Canvas>(
+    output_image: &mut C,
+    leaf: &QuadView,
+    fill: Rgba<u8>,
+    config: &QuadConfig,
+    to_pixel: impl Fn(Rgba<u8>) -> C::Pixel,
+) {
+    for layer in &config.shape_layers {
+        let scale = layer.scale.clamp(0.0, 1.0);
+        let width = (leaf.width as f64 * scale).round() as u32;
+        let height = (leaf.height as f64 * scale).round() as u32;
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let view = QuadView {
+            x: leaf.x + (leaf.width - width) / 2,
+            y: leaf.y + (leaf.height - height) / 2,
+            width,
+            height,
+            ..*leaf
+        };
+        fill_quad_with_color(
+            output_image,
+            &view,
+            to_pixel(layer.color.derive(fill)),
+            layer.shape,
+        );
+    }
+}
+
+/// Fills the quad with a bilinear blend of the top-left, top-right, bottom-left
+/// and bottom-right colors in `corners`, which land exactly on the corner pixels.
+fn fill_quad_gradient<C: Canvas>(
+    output_image: &mut C,
+    quad: &QuadView,
+    corners: [[f64; 4]; 4],
+    to_pixel: impl Fn([f64; 4]) -> C::Pixel,
+) {
+    let (canvas_width, canvas_height) = output_image.dimensions();
+    let span = |size: u32| size.saturating_sub(1).max(1) as f64;
+    let [top_left, top_right, bottom_left, bottom_right] = corners;
+    for y in quad.y..(quad.y + quad.height).min(canvas_height) {
+        let v = (y - quad.y) as f64 / span(quad.height);
+        for x in quad.x..(quad.x + quad.width).min(canvas_width) {
+            let u = (x - quad.x) as f64 / span(quad.width);
+            let color = std::array::from_fn(|channel| {
+                let top = top_left[channel] + (top_right[channel] - top_left[channel]) * u;
+                let bottom =
+                    bottom_left[channel] + (bottom_right[channel] - bottom_left[channel]) * u;
+                top + (bottom - top) * v
+            });
+            output_image.draw_pixel(x, y, to_pixel(color));
+        }
+    }
+}
+
+fn fill_quad_with_color<C: Canvas>(
+    output_image: &mut C,
+    quad: &QuadView,
+    color: C::Pixel,
+    shape: ShapeMode,
+) {
+    // Centered on the middle pixel so the shape stays inside the quad
+    let center = (
+        (quad.x + quad.width.saturating_sub(1) / 2) as i32,
+        (quad.y + quad.height.saturating_sub(1) / 2) as i32,
+    );
+    let width_radius = (quad.width.saturating_sub(1) / 2) as i32;
+    let height_radius = (quad.height.saturating_sub(1) / 2) as i32;
+    match shape {
+        ShapeMode::Rectangle => fill_quad_rect(output_image, quad, color),
+        ShapeMode::Circle => {
+            draw_filled_circle_mut(output_image, center, width_radius.min(height_radius), color)
+        }
+        ShapeMode::Ellipse => {
+            draw_filled_ellipse_mut(output_image, center, width_radius, height_radius, color)
+        }
+        ShapeMode::RoundedRect { radius } => {
+            fill_quad_rounded_rect(output_image, quad, color, radius)
+        }
+    }
+}
+
+/// Fills the quad except for the parts of its corners outside a circle of
+/// `radius`, clamped to half the quad's shorter side. Pixels are tested at
+/// their centers.
+fn fill_quad_rounded_rect<C: Canvas>(
+    output_image: &mut C,
+    quad: &QuadView,
+    color: C::Pixel,
+    radius: u32,
+) {
+    let radius = radius.min(quad.width / 2).min(quad.height / 2) as f64;
+    let (canvas_width, canvas_height) = output_image.dimensions();
+    // Points farther than `radius` outside this inner rectangle are cut off
+    let (inner_left, inner_top) = (quad.x as f64 + radius, quad.y as f64 + radius);
+    let inner_right = (quad.x + quad.width) as f64 - radius;
+    let inner_bottom = (quad.y + quad.height) as f64 - radius;
+    for x in quad.x..(quad.x + quad.width).min(canvas_width) {
+        let center_x = x as f64 + 0.5;
+        let dx = (inner_left - center_x).max(center_x - inner_right).max(0.0);
+        for y in quad.y..(quad.y + quad.height).min(canvas_height) {
+            let center_y = y as f64 + 0.5;
+            let dy = (inner_top - center_y).max(center_y - inner_bottom).max(0.0);
+            if dx * dx + dy * dy <= radius * radius {
+                output_image.draw_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+fn fill_quad_rect<C: Canvas>(output_image: &mut C, quad: &QuadView, color: C::Pixel) {
+    let (canvas_width, canvas_height) = output_image.dimensions();
+    for x in (quad.x)..(quad.x + quad.width) {
+        for y in (quad.y)..(quad.y + quad.height) {
+            if x >= canvas_width || y >= canvas_height {
+                continue;
+            }
+            output_image.draw_pixel(x, y, color);
+        }
+    }
+}
+
+/// Draws the outlines of `leaves`. Neighboring leaves share the line between
+/// them, so each distinct edge is collected once and drawn once. Each edge's
+/// alpha is `color`'s scaled by the fill alpha of the leaf it borders, so
+/// outlines fade over transparent parts of the art; an edge shared by two
+/// leaves takes the more opaque of the two.
+fn draw_outlines<'a, C: Canvas>(
+    output_image: &mut C,
+    leaves: impl IntoIterator<Item = &'a QuadView>,
+    color: Rgba<u8>,
+    config: &QuadConfig,
+    to_pixel: impl Fn(Rgba<u8>) -> C::Pixel,
+) {
+    let (canvas_width, canvas_height) = output_image.dimensions();
+    let mut edges: HashMap<OutlineEdge, u8> = HashMap::new();
+    for leaf in leaves {
+        let fill_alpha = leaf.fill_color(config).0[3];
+        let alpha = (color.0[3] as u32 * fill_alpha as u32 / 255) as u8;
+        for edge in outline_edges(leaf, canvas_width, canvas_height, config) {
+            let edge_alpha = edges.entry(edge).or_insert(0);
+            *edge_alpha = (*edge_alpha).max(alpha);
+        }
+    }
+    // Without border edges, interior edges must also stop short of the outer ring
+    let clip = if config.draw_border_edges {
+        (0, 0, canvas_width, canvas_height)
+    } else {
+        (
+            1,
+            1,
+            canvas_width.saturating_sub(1),
+            canvas_height.saturating_sub(1),
+        )
+    };
+    // Where edges overlap, the most opaque one is drawn last
+    let mut edges: Vec<(OutlineEdge, u8)> = edges.into_iter().collect();
+    edges.sort_unstable_by_key(|&(edge, alpha)| (alpha, edge));
+    for ((start, end, thickness), alpha) in edges {
+        let [r, g, b, _] = color.0;
+        let edge_color = to_pixel(Rgba([r, g, b, alpha]));
+        draw_outline_edge(output_image, start, end, edge_color, thickness, clip);
+    }
+}
+
+/// One side of a leaf's outline, as the inclusive endpoints of a horizontal or
+/// vertical line and the thickness it is drawn at.
+type OutlineEdge = ((u32, u32), (u32, u32), u32);
+
+/// Returns the sides of a leaf's outline to draw. Sides on the canvas border
+/// are left out unless `config.draw_border_edges` is set.
+fn outline_edges(
+    quad: &QuadView,
+    canvas_width: u32,
+    canvas_height: u32,
+    config: &QuadConfig,
+) -> Vec<OutlineEdge> {
+    let (x1, y1) = (quad.x, quad.y);
+    let (x2, y2) = (quad.x + quad.width, quad.y + quad.height);
+    let draw_border_edges = config.draw_border_edges;
+    let thickness = config.effective_outline_thickness(quad.cur_depth);
+
+    let mut edges = Vec::with_capacity(4);
+    if draw_border_edges || y1 > 0 {
+        edges.push(((x1, y1), (x2, y1), thickness));
+    }
+    if draw_border_edges || y2 < canvas_height {
+        edges.push(((x1, y2), (x2, y2), thickness));
+    }
+    if draw_border_edges || x1 > 0 {
+        edges.push(((x1, y1), (x1, y2), thickness));
+    }
+    if draw_border_edges || x2 < canvas_width {
+        edges.push(((x2, y1), (x2, y2), thickness));
+    }
+    edges
+}
+
+/// Draws a horizontal or vertical edge between two inclusive endpoints as a
+/// band `thickness` pixels wide centered on the 1px line, clipped to the
+/// exclusive `(x_start, y_start, x_end, y_end)` bounds of `clip`. Bands thicker
+/// than a pixel that would stick out across the clip bounds, as on the canvas
+/// border, are moved inside them instead, so every edge keeps the same width.
+/// A one-pixel line on the far border lies wholly outside and is dropped.
+fn draw_outline_edge<C: Canvas>(
+    output_image: &mut C,
+    start: (u32, u32),
+    end: (u32, u32),
+    color: C::Pixel,
+    thickness: u32,
+    clip: (u32, u32, u32, u32),
+) {
+    let thickness = thickness.max(1) as i64;
+    let offset = (thickness - 1) / 2;
+    // Across the line the band is moved inside the clip; along it, it is cut off
+    let across = |line: u32, min: u32, max: u32| {
+        let mut start = line as i64 - offset;
+        if thickness > 1 {
+            start = start.min(max as i64 - thickness).max(min as i64);
+        }
+        (start.max(min as i64), (start + thickness).min(max as i64))
+    };
+    let along = |from: u32, to: u32, min: u32, max: u32| {
+        (
+            (from as i64 - offset).max(min as i64),
+            (to as i64 - offset + thickness).min(max as i64),
+        )
+    };
+    let ((left, right), (top, bottom)) = if start.1 == end.1 {
+        (
+            along(start.0, end.0, clip.0, clip.2),
+            across(start.1, clip.1, clip.3),
+        )
+    } else {
+        (
+            across(start.0, clip.0, clip.2),
+            along(start.1, end.1, clip.1, clip.3),
+        )
+    };
+    if left >= right || top >= bottom {
+        return;
+    }
+    let band =
+        Rect::at(left as i32, top as i32).of_size((right - left) as u32, (bottom - top) as u32);
+    draw_filled_rect_mut(output_image, band, color);
+}
+
+/// Per-channel difference from the background below which a pixel is not counted as ink.
+const COVERAGE_TOLERANCE: u8 = 8;
+
+/// Returns the fraction of pixels in `image` that differ from `background`,
+/// which gives a rough estimate of how much ink a print of the art will use.
+pub fn coverage(image: &RgbaImage, background: Rgba<u8>) -> f64 {
+    let pixel_count = image.width() as f64 * image.height() as f64;
+    if pixel_count == 0.0 {
+        return 0.0;
+    }
+    let covered = image
+        .pixels()
+        .filter(|pixel| {
+            pixel
+                .0
+                .iter()
+                .zip(background.0.iter())
+                .any(|(a, b)| a.abs_diff(*b) > COVERAGE_TOLERANCE)
+        })
+        .count();
+    covered as f64 / pixel_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LayerColor, ShapeLayer};
+    use crate::quad::subdivide_nodes;
+
+    fn uniform_quad(width: u32, height: u32, color: Rgba<u8>, config: QuadConfig) -> Quad {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, color));
+        Quad::new(image, 0, 0, width, height, config, 0)
+    }
+
+    #[test]
+    fn half_filled_image_has_half_coverage() {
+        let white = Rgba([255, 255, 255, 255]);
+        let image = RgbaImage::from_fn(40, 20, |x, _| {
+            if x < 20 {
+                Rgba([30, 60, 90, 255])
+            } else {
+                white
+            }
+        });
+        assert!((coverage(&image, white) - 0.5).abs() < 1e-9);
+        // Differences within the tolerance still count as background.
+        let near_white = RgbaImage::from_pixel(10, 10, Rgba([250, 252, 255, 255]));
+        assert_eq!(coverage(&near_white, white), 0.0);
+    }
+
+    #[test]
+    fn region_ids_are_distinct_and_stable() {
+        let config = QuadConfig::default();
+        let leaves = uniform_quad(8, 8, Rgba([40, 40, 40, 255]), config.clone()).subdivide();
+        let first = render_region_ids(&leaves, 8, 8, &config);
+        assert_eq!(first, render_region_ids(&leaves, 8, 8, &config));
+        let corners = [(1, 1), (5, 1), (1, 5), (5, 5)].map(|(x, y)| *first.get_pixel(x, y));
+        for (i, a) in corners.iter().enumerate() {
+            for b in &corners[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        assert_eq!(region_id_color(4, 0, 1), corners[1]);
+    }
+
+    #[test]
+    fn raw_buffer_matches_rendered_image() {
+        let config = QuadConfig::default();
+        let leaves = uniform_quad(6, 5, Rgba([10, 20, 30, 200]), config.clone()).subdivide();
+        let image = render_to_image(&leaves, 6, 5, &config);
+        let rgba = render_raw(&leaves, 6, 5, PixelLayout::Rgba, &config);
+        assert_eq!(rgba.len(), 6 * 5 * 4);
+        assert_eq!(rgba, image.as_raw().as_slice());
+
+        let bgra = render_raw(&leaves, 6, 5, PixelLayout::Bgra, &config);
+        for (b, a) in bgra.chunks_exact(4).zip(rgba.chunks_exact(4)) {
+            assert_eq!(b, [a[2], a[1], a[0], a[3]]);
+        }
+    }
+
+    #[test]
+    fn render_at_leaves_outside_pixels_untouched() {
+        let backdrop = Rgba([1, 2, 3, 4]);
+        let mut canvas = RgbaImage::from_pixel(40, 30, backdrop);
+        let config = QuadConfig::default();
+        let leaves = uniform_quad(8, 6, Rgba([200, 100, 50, 255]), config.clone()).subdivide();
+        render_at(&leaves, 8, 6, &mut canvas, 10, 10, &config);
+
+        let art = render_to_image(&leaves, 8, 6, &config);
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            if (10..18).contains(&x) && (10..16).contains(&y) {
+                assert_eq!(pixel, art.get_pixel(x - 10, y - 10));
+            } else {
+                assert_eq!(*pixel, backdrop, "({}, {})", x, y);
+            }
+        }
+
+        // Leaves running past the canvas edge are clipped
+        render_at(&leaves, 8, 6, &mut canvas, 36, 26, &config);
+        assert_eq!(canvas.get_pixel(39, 29), art.get_pixel(3, 3));
+    }
+
+    fn base64_decode(encoded: &str) -> Vec<u8> {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut bits = 0u32;
+        let mut bit_count = 0;
+        let mut decoded = Vec::new();
+        for byte in encoded.bytes().take_while(|byte| *byte != b'=') {
+            let value = ALPHABET.iter().position(|c| *c == byte).unwrap() as u32;
+            bits = (bits << 6) | value;
+            bit_count += 6;
+            if bit_count >= 8 {
+                bit_count -= 8;
+                decoded.push((bits >> bit_count) as u8);
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn data_uri_holds_a_png_of_the_right_size() {
+        let config = QuadConfig::default();
+        let leaves = uniform_quad(7, 5, Rgba([90, 180, 30, 255]), config.clone()).subdivide();
+        let uri = render_data_uri(&leaves, 7, 5, &config).unwrap();
+        let payload = uri.strip_prefix("data:image/png;base64,").unwrap();
+        let decoded =
+            image::load_from_memory_with_format(&base64_decode(payload), image::ImageFormat::Png)
+                .unwrap()
+                .to_rgba8();
+        assert_eq!(decoded, render_to_image(&leaves, 7, 5, &config));
+
+        assert!(matches!(
+            render_data_uri(&[], 0, 0, &config),
+            Err(QuadError::EmptyImage)
+        ));
+    }
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64_encode(b"Man"), "TWFu");
+        assert_eq!(base64_encode(b"Ma"), "TWE=");
+        assert_eq!(base64_encode(b"M"), "TQ==");
+        assert_eq!(base64_decode("TWE="), b"Ma");
+    }
+
+    #[test]
+    fn feathered_boundaries_blend_and_centers_stay_flat() {
+        let (black, white) = (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]));
+        let image = RgbaImage::from_fn(32, 16, |x, _| if x < 16 { black } else { white });
+        let config = QuadConfig {
+            edge_feather: 4,
+            outline_color: None,
+            ..QuadConfig::default()
+        };
+        let quad = Quad::new(
+            DynamicImage::ImageRgba8(image),
+            0,
+            0,
+            32,
+            16,
+            config.clone(),
+            0,
+        );
+        let leaves = quad.subdivide();
+        let output = render_to_image(&leaves, 32, 16, &config);
+
+        for x in [15, 16] {
+            let value = output.get_pixel(x, 4).0[0];
+            assert!(value > 64 && value < 192, "{} at x {}", value, x);
+        }
+        assert!(output.get_pixel(14, 4).0[0] < output.get_pixel(15, 4).0[0]);
+        assert_eq!(*output.get_pixel(8, 4), black);
+        assert_eq!(*output.get_pixel(24, 4), white);
+
+        let flat = QuadConfig {
+            edge_feather: 0,
+            ..config
+        };
+        assert_eq!(
+            *render_to_image(&leaves, 32, 16, &flat).get_pixel(15, 4),
+            black
+        );
+    }
+
+    #[test]
+    fn border_edges_off_keeps_the_outer_ring_clear() {
+        let outline = Rgba([255, 0, 0, 255]);
+        for outline_thickness in [1, 3] {
+            let config = QuadConfig {
+                draw_border_edges: false,
+                outline_color: Some(outline),
+                outline_thickness,
+                ..QuadConfig::default()
+            };
+            let leaves: Vec<Quad> = uniform_quad(64, 64, Rgba([0, 0, 200, 255]), config.clone())
+                .subdivide()
+                .iter()
+                .flat_map(Quad::subdivide)
+                .collect();
+            let image = render_to_image(&leaves, 64, 64, &config);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                if x == 0 || y == 0 || x == 63 || y == 63 {
+                    assert_ne!(
+                        *pixel, outline,
+                        "({}, {}) at thickness {}",
+                        x, y, outline_thickness
+                    );
+                }
+            }
+            // Interior edges are still drawn up to the ring
+            assert_eq!(*image.get_pixel(16, 1), outline);
+            assert_eq!(*image.get_pixel(1, 32), outline);
+
+            let mut streamed = Vec::new();
+            write_png_streaming(&leaves, 64, 64, &config, &mut streamed).unwrap();
+            let streamed = image::load_from_memory(&streamed).unwrap().to_rgba8();
+            assert_eq!(streamed, image);
+        }
+    }
+
+    #[test]
+    fn comparison_is_about_twice_as_wide() {
+        let config = QuadConfig::default();
+        let original =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 30, Rgba([9, 9, 9, 255])));
+        let leaves = uniform_quad(40, 30, Rgba([9, 9, 9, 255]), config.clone()).subdivide();
+        let comparison = render_comparison(&original, &leaves, 40, 30, &config);
+        assert_eq!(comparison.dimensions(), (80 + COMPARISON_DIVIDER_WIDTH, 30));
+        assert_eq!(*comparison.get_pixel(41, 10), Rgba([255, 255, 255, 255]));
+
+        // A taller original is scaled down to the art's height, keeping its aspect
+        let tall = RgbaImage::from_pixel(80, 60, Rgba([9, 9, 9, 255]));
+        let comparison =
+            render_comparison(&DynamicImage::ImageRgba8(tall), &leaves, 40, 30, &config);
+        assert_eq!(comparison.dimensions(), (80 + COMPARISON_DIVIDER_WIDTH, 30));
+    }
+
+    #[test]
+    fn empty_canvas_renders_without_leaves() {
+        let config = QuadConfig::default();
+        let leaves = uniform_quad(4, 4, Rgba([1, 2, 3, 255]), config.clone()).subdivide();
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let image = render_to_image(&leaves, width, height, &config);
+            assert_eq!(image.dimensions(), (width, height));
+        }
+    }
+
+    /// Decodes with libwebp itself rather than the image crate's decoder.
+    fn decode_webp(bytes: &[u8]) -> RgbaImage {
+        let decoded = webp::Decoder::new(bytes).decode().unwrap();
+        let (width, height) = (decoded.width(), decoded.height());
+        if decoded.is_alpha() {
+            RgbaImage::from_raw(width, height, decoded.to_vec()).unwrap()
+        } else {
+            let rgb = image::RgbImage::from_raw(width, height, decoded.to_vec()).unwrap();
+            DynamicImage::ImageRgb8(rgb).to_rgba8()
+        }
+    }
+
+    fn encode_webp(image: &RgbaImage, quality: Option<u8>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_webp(image, quality, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn gradient_art(width: u32, height: u32) -> RgbaImage {
+        let source = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+        });
+        // No outlines, as lossy WebP smears one-pixel lines
+        let config = QuadConfig {
+            outline_color: None,
+            ..QuadConfig::default()
+        };
+        let quad = Quad::new(
+            DynamicImage::ImageRgba8(source),
+            0,
+            0,
+            width,
+            height,
+            config.clone(),
+            0,
+        );
+        render_to_image(&subdivide_nodes(quad), width, height, &config)
+    }
+
+    #[test]
+    fn lossless_webp_decodes_to_the_same_pixels() {
+        let mut translucent = gradient_art(37, 21);
+        for (x, _, pixel) in translucent.enumerate_pixels_mut() {
+            pixel[3] = (x * 7) as u8;
+        }
+        for image in [gradient_art(64, 48), translucent] {
+            let bytes = encode_webp(&image, None);
+            for decoded in [
+                decode_webp(&bytes),
+                image::load_from_memory(&bytes).unwrap().to_rgba8(),
+            ] {
+                assert_eq!(decoded.dimensions(), image.dimensions());
+                // libwebp may drop the color of fully transparent pixels
+                assert!(image
+                    .pixels()
+                    .zip(decoded.pixels())
+                    .all(|(a, b)| a == b || (a[3] == 0 && b[3] == 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn lossy_webp_is_close_and_shrinks_with_quality() {
+        let mut image = gradient_art(64, 48);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            pixel[3] = if x < 8 { 0 } else { (x * 3 + y) as u8 };
+        }
+        let mean_error = |decoded: &RgbaImage| {
+            let (sum, count) = image
+                .pixels()
+                .zip(decoded.pixels())
+                .filter(|(a, _)| a[3] > 0)
+                .flat_map(|(a, b)| (0..3).map(move |c| a[c].abs_diff(b[c]) as f64))
+                .fold((0.0, 0.0), |(sum, count), error| (sum + error, count + 1.0));
+            sum / count
+        };
+
+        let (fine, coarse) = (encode_webp(&image, Some(90)), encode_webp(&image, Some(5)));
+        assert!(coarse.len() < fine.len());
+        let decoded = decode_webp(&fine);
+        assert_eq!(decoded.dimensions(), (64, 48));
+        assert!(mean_error(&decoded) < 4.0, "{}", mean_error(&decoded));
+        assert!(mean_error(&decode_webp(&coarse)) > mean_error(&decoded));
+        // libwebp keeps the alpha plane lossless even for lossy color
+        assert!(image
+            .pixels()
+            .zip(decoded.pixels())
+            .all(|(a, b)| a[3] == b[3]));
+    }
+
+    #[test]
+    fn circle_layer_is_drawn_over_the_fill() {
+        let fill = Rgba([200, 40, 40, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let config = QuadConfig {
+            outline_color: None,
+            shape_layers: vec![ShapeLayer {
+                shape: ShapeMode::Circle,
+                scale: 0.5,
+                color: LayerColor::Fixed(white),
+            }],
+            ..QuadConfig::default()
+        };
+        // Four 16x16 cells
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, y| {
+            if (x < 16) == (y < 16) {
+                fill
+            } else {
+                Rgba([40, 40, 200, 255])
+            }
+        }));
+        let leaves = subdivide_nodes(Quad::new(image, 0, 0, 32, 32, config.clone(), 0));
+        assert_eq!(leaves.len(), 4);
+        let output = render_to_image(&leaves, 32, 32, &config);
+        for (x, y) in [(0, 0), (16, 16)] {
+            assert_eq!(*output.get_pixel(x + 7, y + 7), white);
+            assert_eq!(*output.get_pixel(x, y), fill);
+            assert_eq!(*output.get_pixel(x + 15, y + 15), fill);
+        }
+        assert_eq!(*output.get_pixel(23, 7), white);
+        assert_eq!(*output.get_pixel(16, 0), Rgba([40, 40, 200, 255]));
+
+        let lighter = LayerColor::Lighten(0.5).derive(Rgba([100, 0, 200, 128]));
+        assert_eq!(lighter, Rgba([178, 128, 228, 128]));
+    }
+
+    #[test]
+    fn thick_outlines_are_as_wide_on_the_border_as_inside() {
+        let outline = Rgba([255, 0, 0, 255]);
+        for outline_thickness in [2, 3, 4] {
+            let config = QuadConfig {
+                outline_color: Some(outline),
+                outline_thickness,
+                ..QuadConfig::default()
+            };
+            let leaves = uniform_quad(32, 32, Rgba([0, 0, 200, 255]), config.clone()).subdivide();
+            let image = render_to_image(&leaves, 32, 32, &config);
+            // Runs of outline pixels across the middle of the top row of leaves
+            let mut runs = Vec::new();
+            let mut run = 0;
+            for x in 0..32 {
+                if *image.get_pixel(x, 8) == outline {
+                    run += 1;
+                } else if run > 0 {
+                    runs.push(run);
+                    run = 0;
+                }
+            }
+            if run > 0 {
+                runs.push(run);
+            }
+            // Left border, shared middle edge, right border
+            assert_eq!(
+                runs,
+                vec![outline_thickness; 3],
+                "at thickness {}",
+                outline_thickness
+            );
+
+            let mut streamed = Vec::new();
+            write_png_streaming(&leaves, 32, 32, &config, &mut streamed).unwrap();
+            let streamed = image::load_from_memory(&streamed).unwrap().to_rgba8();
+            assert_eq!(streamed, image);
+        }
+    }
+
+    #[test]
+    fn outlines_fade_over_transparent_leaves() {
+        let outline = Rgba([255, 0, 0, 255]);
+        let config = QuadConfig {
+            outline_color: Some(outline),
+            background_color: Rgba([0, 0, 0, 0]),
+            ..QuadConfig::default()
+        };
+        // An opaque left half and a half-transparent right half
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, _| {
+            if x < 16 {
+                Rgba([0, 0, 200, 255])
+            } else {
+                Rgba([0, 200, 0, 128])
+            }
+        }));
+        let leaves = subdivide_nodes(Quad::new(image, 0, 0, 32, 32, config.clone(), 0));
+        assert_eq!(leaves.len(), 4);
+        let output = render_to_image(&leaves, 32, 32, &config);
+        // Top border over the opaque and over the translucent half
+        assert_eq!(*output.get_pixel(8, 0), outline);
+        assert_eq!(*output.get_pixel(24, 0), Rgba([255, 0, 0, 128]));
+        // The shared edge in the middle keeps the more opaque side's alpha
+        assert_eq!(*output.get_pixel(16, 8), outline);
+
+        let svg = render_svg(&leaves, 32, 32, &config);
+        assert!(svg.contains("stroke-opacity=\"0.502\""));
+        assert!(svg.contains("fill=\"#00c800\" fill-opacity=\"0.502\""));
+        assert!(!svg.contains("fill=\"#0000c8\" fill-opacity"));
+    }
+}
@@ -0,0 +1,189 @@
+use image::Rgba;
+
+use crate::config::{FillMode, QuadConfig, ShapeMode, Symmetry};
+use crate::dither::{dither_leaves, without_color_processing};
+use crate::quad::{Quad, QuadView};
+use crate::render::to_output_space;
+
+/// Renders the leaves as an SVG document with one outlined `<rect>` per leaf,
+/// so the art stays crisp at any zoom. With `config.symmetry` the leaves are
+/// grouped and the group is reused with a reflecting transform for each mirrored
+/// copy.
+pub fn render_svg(
+    quadtree_leaves: &[Quad],
+    image_width: u32,
+    image_height: u32,
+    config: &QuadConfig,
+) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+        image_width, image_height
+    );
+    if config.background_color.0[3] > 0 {
+        svg.push_str(&format!(
+            "  <rect width=\"100%\" height=\"100%\" {}/>\n",
+            fill_attributes(config.background_color)
+        ));
+    }
+    if config.symmetry.is_some() {
+        svg.push_str("  <g id=\"leaves\">\n");
+    }
+    let (views, config) = if config.dithers_palette() {
+        (
+            dither_leaves(quadtree_leaves, config),
+            without_color_processing(config),
+        )
+    } else {
+        (
+            quadtree_leaves
+                .iter()
+                .map(Quad::view)
+                .collect::<Vec<QuadView>>(),
+            config.clone(),
+        )
+    };
+    let config = &config;
+    for leaf in views {
+        let leaf = to_output_space(leaf, config);
+        if leaf.x >= image_width || leaf.y >= image_height {
+            continue;
+        }
+        let width = leaf.width.min(image_width - leaf.x);
+        let height = leaf.height.min(image_height - leaf.y);
+        let fill = fill_attributes(leaf.fill_color(config));
+        let (cx, cy) = (
+            leaf.x as f64 + width as f64 / 2.0,
+            leaf.y as f64 + height as f64 / 2.0,
+        );
+        let rect_fill = match config.shape_mode {
+            _ if config.fill_mode == FillMode::Wireframe => String::from("fill=\"none\""),
+            ShapeMode::Rectangle => fill,
+            shape => {
+                svg.push_str(&shape_element(
+                    shape,
+                    leaf.x as f64,
+                    leaf.y as f64,
+                    width as f64,
+                    height as f64,
+                    &fill,
+                ));
+                String::from("fill=\"none\"")
+            }
+        };
+        let stroke = match config.outline_color {
+            Some(outline_color) => {
+                let mut stroke = format!(
+                    " stroke=\"{}\" stroke-width=\"{}\"",
+                    hex_color(outline_color),
+                    config.effective_outline_thickness(leaf.cur_depth)
+                );
+                // Outlines fade with the leaf they border, as in raster output
+                let alpha = outline_color.0[3] as f64 * leaf.fill_color(config).0[3] as f64;
+                if alpha < 255.0 * 255.0 {
+                    stroke.push_str(&format!(
+                        " stroke-opacity=\"{:.3}\"",
+                        alpha / (255.0 * 255.0)
+                    ));
+                }
+                stroke
+            }
+            None => String::new(),
+        };
+        svg.push_str(&format!(
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}{}/>\n",
+            leaf.x, leaf.y, width, height, rect_fill, stroke
+        ));
+        if config.fill_mode != FillMode::Wireframe {
+            for layer in &config.shape_layers {
+                let scale = layer.scale.clamp(0.0, 1.0);
+                let (layer_width, layer_height) = (width as f64 * scale, height as f64 * scale);
+                svg.push_str(&shape_element(
+                    layer.shape,
+                    cx - layer_width / 2.0,
+                    cy - layer_height / 2.0,
+                    layer_width,
+                    layer_height,
+                    &fill_attributes(layer.color.derive(leaf.fill_color(config))),
+                ));
+            }
+        }
+    }
+    if let Some(symmetry) = config.symmetry {
+        svg.push_str("  </g>\n");
+        let mut reflections = Vec::new();
+        if symmetry.mirrors_horizontally() {
+            reflections.push((-1, 1));
+        }
+        if symmetry.mirrors_vertically() {
+            reflections.push((1, -1));
+        }
+        if symmetry == Symmetry::Quad {
+            reflections.push((-1, -1));
+        }
+        for (scale_x, scale_y) in reflections {
+            let translate_x = if scale_x < 0 { image_width } else { 0 };
+            let translate_y = if scale_y < 0 { image_height } else { 0 };
+            svg.push_str(&format!(
+                "  <use href=\"#leaves\" transform=\"matrix({} 0 0 {} {} {})\"/>\n",
+                scale_x, scale_y, translate_x, translate_y
+            ));
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Returns the element drawing `shape` in the box at `(x, y)` of the given
+/// size, with `fill` holding its fill attributes.
+fn shape_element(shape: ShapeMode, x: f64, y: f64, width: f64, height: f64, fill: &str) -> String {
+    let (cx, cy) = (x + width / 2.0, y + height / 2.0);
+    match shape {
+        ShapeMode::Rectangle => format!(
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>\n",
+            x, y, width, height, fill
+        ),
+        ShapeMode::Circle => format!(
+            "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" {}/>\n",
+            cx,
+            cy,
+            width.min(height) / 2.0,
+            fill
+        ),
+        ShapeMode::Ellipse => format!(
+            "  <ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" {}/>\n",
+            cx,
+            cy,
+            width / 2.0,
+            height / 2.0,
+            fill
+        ),
+        ShapeMode::RoundedRect { radius } => format!(
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\" {}/>\n",
+            x,
+            y,
+            width,
+            height,
+            (radius as f64)
+                .min((width / 2.0).floor())
+                .min((height / 2.0).floor()),
+            fill
+        ),
+    }
+}
+
+/// Returns the `fill` attribute for `color`, plus `fill-opacity` when it is
+/// translucent, since hex colors carry no alpha.
+fn fill_attributes(color: Rgba<u8>) -> String {
+    let mut attributes = format!("fill=\"{}\"", hex_color(color));
+    if color.0[3] < 255 {
+        attributes.push_str(&format!(
+            " fill-opacity=\"{:.3}\"",
+            color.0[3] as f64 / 255.0
+        ));
+    }
+    attributes
+}
+
+pub(crate) fn hex_color(color: Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0[0], color.0[1], color.0[2])
+}
@@ -0,0 +1,148 @@
+use image::Rgba;
+
+use crate::quad::Quad;
+
+/// A quad in the full subdivision tree. Internal nodes keep their own average
+/// color so the hierarchy can be inspected at any level.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub color: Rgba<u8>,
+    pub depth: u32,
+    pub children: Option<Vec<Node>>,
+}
+
+impl Node {
+    /// Whether the point `(x, y)` lies inside this node's bounds.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_none()
+    }
+}
+
+/// The full subdivision tree of an image, for walking the hierarchy or
+/// looking up the leaf under a point.
+#[derive(Clone, Debug)]
+pub struct QuadTree {
+    pub root: Node,
+}
+
+impl QuadTree {
+    /// Returns the leaves in depth-first order.
+    pub fn leaves(&self) -> Vec<&Node> {
+        let mut leaves: Vec<&Node> = Vec::new();
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            match &node.children {
+                Some(children) => stack.extend(children.iter().rev()),
+                None => leaves.push(node),
+            }
+        }
+        leaves
+    }
+
+    /// Returns the leaf containing the point `(x, y)`, descending one level at
+    /// a time, or `None` if the point is outside the tree.
+    pub fn leaf_at(&self, x: u32, y: u32) -> Option<&Node> {
+        let mut node = &self.root;
+        if !node.contains(x, y) {
+            return None;
+        }
+        while let Some(children) = &node.children {
+            node = children.iter().find(|child| child.contains(x, y))?;
+        }
+        Some(node)
+    }
+}
+
+/// Subdivides `quad` into a `QuadTree`, keeping the parent/child links.
+pub fn build_tree(quad: Quad) -> QuadTree {
+    QuadTree {
+        root: build_full_tree(quad),
+    }
+}
+
+/// Subdivides `quad` like `subdivide_nodes` but keeps the parent/child links
+/// instead of flattening the result to leaves.
+pub fn build_full_tree(quad: Quad) -> Node {
+    let children = if quad.should_subdivide() {
+        Some(quad.split().into_iter().map(build_full_tree).collect())
+    } else {
+        None
+    };
+    Node {
+        x: quad.x,
+        y: quad.y,
+        width: quad.width,
+        height: quad.height,
+        color: quad.color,
+        depth: quad.cur_depth,
+        children,
+    }
+}
+
+/// Prints the tree in a Newick-like form: children are listed in parentheses
+/// before each node's `<x,y>WxH` bounds, and the depth follows the colon.
+pub fn to_tree_string(node: &Node) -> String {
+    let mut tree_string = String::new();
+    write_node(node, &mut tree_string);
+    tree_string.push(';');
+    tree_string
+}
+
+fn write_node(node: &Node, tree_string: &mut String) {
+    if let Some(children) = &node.children {
+        tree_string.push('(');
+        for (i, child) in children.iter().enumerate() {
+            if i > 0 {
+                tree_string.push(',');
+            }
+            write_node(child, tree_string);
+        }
+        tree_string.push(')');
+    }
+    tree_string.push_str(&format!(
+        "<{},{}>{}x{}:{}",
+        node.x, node.y, node.width, node.height, node.depth
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuadConfig;
+    use image::{DynamicImage, RgbaImage};
+
+    #[test]
+    fn one_level_tree_string_has_four_children() {
+        let image = RgbaImage::from_fn(4, 4, |x, y| match (x < 2, y < 2) {
+            (true, true) => Rgba([255, 0, 0, 255]),
+            (false, true) => Rgba([0, 255, 0, 255]),
+            (true, false) => Rgba([0, 0, 255, 255]),
+            (false, false) => Rgba([255, 255, 255, 255]),
+        });
+        let config = QuadConfig {
+            max_depth: 1,
+            size_threshold: 1,
+            ..QuadConfig::default()
+        };
+        let quad = Quad::new(DynamicImage::ImageRgba8(image), 0, 0, 4, 4, config, 0);
+        let root = build_full_tree(quad);
+
+        let children = root.children.as_ref().unwrap();
+        assert_eq!(children.len(), 4);
+        assert!(children
+            .iter()
+            .all(|child| child.children.is_none() && child.depth == 1));
+        assert_eq!(
+            to_tree_string(&root),
+            "(<0,0>2x2:1,<2,0>2x2:1,<0,2>2x2:1,<2,2>2x2:1)<0,0>4x4:0;"
+        );
+    }
+}
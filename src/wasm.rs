@@ -0,0 +1,44 @@
+use wasm_bindgen::prelude::*;
+
+use crate::process;
+
+/// Browser entry point for `process::process_image`: runs the pipeline on raw
+/// RGBA pixels and returns the art as raw RGBA pixels of the same size, without
+/// touching the filesystem. Throws the error message if `data` does not match
+/// the dimensions or the image is empty.
+#[wasm_bindgen]
+pub fn process_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    max_depth: u32,
+    color_threshold: f64,
+    size_threshold: u32,
+) -> Result<Vec<u8>, String> {
+    process::process_image(
+        data,
+        width,
+        height,
+        max_depth,
+        color_threshold,
+        size_threshold,
+    )
+    .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_matches_the_native_pipeline() {
+        let pixels: Vec<u8> = (0..8 * 6)
+            .flat_map(|i| [i as u8 * 5, 40, 200, 255])
+            .collect();
+        assert_eq!(
+            process_image(&pixels, 8, 6, 7, 10.0, 2).unwrap(),
+            process::process_image(&pixels, 8, 6, 7, 10.0, 2).unwrap()
+        );
+        assert!(process_image(&pixels, 9, 6, 7, 10.0, 2).is_err());
+    }
+}